@@ -0,0 +1,262 @@
+use crate::ast::lexer::TextSpan;
+use crate::ast::{
+    ASTBinaryExpression, ASTBinaryOperator, ASTBinaryOperatorKind, ASTCallExpression,
+    ASTExpression, ASTExpressionKind, ASTFactorialExpression, ASTFunctionDeclaration,
+    ASTNumberExpression, ASTParenthesizedExpression, ASTStartEndExpression, ASTUnaryExpression,
+    ASTVariableDeclaration, ASTVariableExpression, ASTVisitor,
+};
+use crate::prelude::*;
+
+// Finds `/` or `//` whose right operand is the literal `0`. Only literals are
+// considered, so `1 / (2 - 2)` is not reported.
+#[derive(Default)]
+pub struct LiteralDivisionByZeroFinder {
+    pub found: bool,
+}
+
+impl ASTVisitor for LiteralDivisionByZeroFinder {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        let is_division = matches!(
+            binary_expression.operator.kind,
+            ASTBinaryOperatorKind::Divide | ASTBinaryOperatorKind::IntegerDivide
+        );
+        if is_division {
+            if let ASTExpressionKind::Number(number) = &binary_expression.right.kind {
+                if number.number == 0.0 {
+                    self.found = true;
+                }
+            }
+        }
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+    }
+}
+
+// Static estimate of the arithmetic work in a program: every binary operation
+// counts once and every call counts once on top of its arguments. Function
+// bodies are not expanded, so recursion does not affect the count.
+#[derive(Default)]
+pub struct OperationCounter {
+    pub count: usize,
+}
+
+impl ASTVisitor for OperationCounter {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.count += 1;
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.count += 1;
+        self.visit_expression(&unary_expression.operand);
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.count += 1;
+        self.visit_expression(&factorial_expression.expression);
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        self.count += 1;
+        for argument in &call.arguments {
+            self.visit_expression(argument);
+        }
+    }
+}
+
+// Every binary operator kind that appears anywhere in the program, function
+// bodies included.
+#[derive(Default)]
+pub struct OperatorCollector {
+    pub operators: HashSet<ASTBinaryOperatorKind>,
+}
+
+impl ASTVisitor for OperatorCollector {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.operators
+            .insert(binary_expression.operator.kind.clone());
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+    }
+}
+
+// Deepest nesting of parentheses and `{}` blocks in any statement, so `((x))`
+// has depth 2 and `x` has depth 0.
+#[derive(Default)]
+pub struct ParenDepthFinder {
+    depth: usize,
+    pub max_depth: usize,
+}
+
+impl ParenDepthFinder {
+    fn nested(&mut self, visit: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        visit(self);
+        self.depth -= 1;
+    }
+}
+
+impl ASTVisitor for ParenDepthFinder {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_parenthesized_expression(
+        &mut self,
+        parenthesized_expression: &ASTParenthesizedExpression,
+    ) {
+        self.nested(|finder| finder.visit_expression(&parenthesized_expression.expression));
+    }
+
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        self.nested(|finder| {
+            for statement in &start_end_expression.statements {
+                finder.visit_statement(statement);
+            }
+        });
+    }
+}
+
+// Declarations that hide a binding from an enclosing scope. A function's
+// parameters and every block open a scope of their own; redeclaring a name in the same scope
+// is a reassignment and is not reported.
+pub struct ShadowFinder {
+    scopes: Vec<Vec<String>>,
+    pub shadowed: Vec<(String, TextSpan)>,
+}
+
+impl Default for ShadowFinder {
+    fn default() -> Self {
+        Self {
+            scopes: vec![Vec::new()],
+            shadowed: Vec::new(),
+        }
+    }
+}
+
+impl ShadowFinder {
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scope.iter().any(|bound| bound == name))
+    }
+
+    fn is_bound_outside(&self, name: &str) -> bool {
+        let enclosing = &self.scopes[..self.scopes.len() - 1];
+        enclosing
+            .iter()
+            .any(|scope| scope.iter().any(|bound| bound == name))
+    }
+
+    fn declare(&mut self, name: &str, span: &TextSpan) {
+        if self.is_bound_outside(name) {
+            self.shadowed.push((name.to_string(), span.clone()));
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(name.to_string());
+        }
+    }
+}
+
+impl ASTVisitor for ShadowFinder {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.visit_expression(&declaration.initializer);
+        // An assignment to a name already in scope updates that binding.
+        if declaration.assignment && self.is_bound(&declaration.name) {
+            return;
+        }
+        self.declare(&declaration.name, &declaration.span);
+    }
+
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.scopes.push(Vec::new());
+        for parameter in &declaration.parameters {
+            self.declare(&parameter.name, &parameter.span);
+        }
+        self.visit_expression(&declaration.body);
+        self.scopes.pop();
+    }
+
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        self.scopes.push(Vec::new());
+        for statement in &start_end_expression.statements {
+            self.visit_statement(statement);
+        }
+        self.scopes.pop();
+    }
+}
+
+// One line per operator whose operand is another operator, saying which of
+// the two binds first and why: higher precedence, grouping at equal
+// precedence, or parentheses. Lines come innermost first, in the order the
+// operators are applied.
+#[derive(Default)]
+pub struct PrecedenceExplainer {
+    pub lines: Vec<String>,
+}
+
+impl PrecedenceExplainer {
+    fn explain(&mut self, outer: &ASTBinaryOperator, operand: &ASTExpression, is_left: bool) {
+        let (inner, parenthesized) = match &operand.kind {
+            ASTExpressionKind::Binary(binary) => (&binary.operator, false),
+            ASTExpressionKind::Parenthesized(ASTParenthesizedExpression { expression }) => {
+                match &expression.kind {
+                    ASTExpressionKind::Binary(binary) => (&binary.operator, true),
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
+        let describe = |operator: &ASTBinaryOperator| {
+            format!("`{}` (prec {})", operator.kind, operator.precedence())
+        };
+        let mut line = if parenthesized {
+            format!(
+                "parentheses make {} bind before {}",
+                describe(inner),
+                describe(outer)
+            )
+        } else {
+            format!("{} binds before {}", describe(inner), describe(outer))
+        };
+        if !parenthesized && inner.precedence() == outer.precedence() {
+            line.push_str(if is_left {
+                ", grouping left to right"
+            } else {
+                ", grouping right to left"
+            });
+        }
+        self.lines.push(line);
+    }
+}
+
+impl ASTVisitor for PrecedenceExplainer {
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, _variable: &ASTVariableExpression) {}
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+        self.explain(&binary_expression.operator, &binary_expression.left, true);
+        self.explain(&binary_expression.operator, &binary_expression.right, false);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::ast::lexer::TextSpan;
+use crate::ast::{
+    ASTConditionalExpression, ASTExpression, ASTExpressionKind, ASTFunctionDeclaration,
+    ASTMutVisitor, ASTNumberExpression, ASTStartEndExpression, ASTStatement, ASTStatementKind,
+    ASTVariableDeclaration, ASTVariableExpression, ASTVisitor, Ast, ANSWER_VARIABLE,
+};
+use crate::prelude::*;
+
+// Common subexpression elimination: a subtree that occurs more than once in
+// a statement is computed once, by a `var` declaration placed just before
+// the statement, so `(x + 1) * (x + 1)` becomes
+// `var tmp0 = x + 1; (tmp0) * (tmp0)`.
+//
+// Only subtrees that are always evaluated are shared, so nothing inside a
+// conditional branch is hoisted out of it. Calls and blocks can have effects,
+// such as `rand()`, a `print` or assigning a variable another occurrence
+// reads, so a statement containing one anywhere is left as written.
+// Statements that read `ans` are skipped too, since the extra declarations
+// would change its value, and function bodies are not touched.
+pub fn eliminate(ast: &Ast) -> Ast {
+    let mut names = NameCollector::default();
+    ast.visit(&mut names);
+    let mut temporaries = Temporaries {
+        used: names.names,
+        next: 0,
+    };
+    let mut result = Ast::new();
+    for statement in &ast.statements {
+        let mut statement = statement.clone();
+        let repeated = repeated_subtrees(&statement);
+        if !repeated.is_empty() {
+            let mut hoister = Hoister {
+                repeated,
+                hoisted: HashMap::new(),
+                temporaries: &mut temporaries,
+                declarations: Vec::new(),
+            };
+            hoister.visit_statement_mut(&mut statement);
+            for declaration in hoister.declarations {
+                result.add_statement(declaration);
+            }
+        }
+        result.add_statement(statement);
+    }
+    result
+}
+
+fn repeated_subtrees(statement: &ASTStatement) -> HashSet<ASTExpression> {
+    if matches!(statement.kind, ASTStatementKind::FunctionDeclaration(_))
+        || statement
+            .expressions()
+            .into_iter()
+            .any(|expression| reads_answer(expression) || contains_call_or_block(expression))
+    {
+        return HashSet::new();
+    }
+    let mut counts: HashMap<&ASTExpression, usize> = HashMap::new();
+    let mut pending = statement.expressions();
+    while let Some(expression) = pending.pop() {
+        if is_shareable(expression) {
+            *counts.entry(expression).or_insert(0) += 1;
+        }
+        match &expression.kind {
+            ASTExpressionKind::Conditional(conditional) => pending.push(&conditional.condition),
+            ASTExpressionKind::StartEnd(_) => {}
+            _ => pending.extend(expression.children()),
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(expression, _)| expression.clone())
+        .collect()
+}
+
+// Leaves are as cheap to repeat as a temporary, and parentheses are looked
+// through, so `(x + 1)` and `x + 1` share.
+fn is_shareable(expression: &ASTExpression) -> bool {
+    matches!(
+        expression.kind,
+        ASTExpressionKind::Binary(_)
+            | ASTExpressionKind::Unary(_)
+            | ASTExpressionKind::Absolute(_)
+            | ASTExpressionKind::Factorial(_)
+            | ASTExpressionKind::Conditional(_)
+    )
+}
+
+// Looks into conditional branches too: a block in either branch may assign a
+// variable that the operands around the conditional read.
+fn contains_call_or_block(expression: &ASTExpression) -> bool {
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        if matches!(
+            expression.kind,
+            ASTExpressionKind::Call(_) | ASTExpressionKind::StartEnd(_)
+        ) {
+            return true;
+        }
+        pending.extend(expression.children());
+    }
+    false
+}
+
+fn reads_answer(expression: &ASTExpression) -> bool {
+    let mut pending = vec![expression];
+    while let Some(expression) = pending.pop() {
+        if let ASTExpressionKind::Variable(variable) = &expression.kind {
+            if variable.name == ANSWER_VARIABLE {
+                return true;
+            }
+        }
+        pending.extend(expression.children());
+    }
+    false
+}
+
+// Hands out `tmp0`, `tmp1`, ..., skipping any name the program already uses.
+struct Temporaries {
+    used: HashSet<String>,
+    next: usize,
+}
+
+impl Temporaries {
+    fn fresh(&mut self) -> String {
+        loop {
+            let name = format!("tmp{}", self.next);
+            self.next += 1;
+            if self.used.insert(name.clone()) {
+                return name;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct NameCollector {
+    names: HashSet<String>,
+}
+
+impl ASTVisitor for NameCollector {
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        for parameter in &declaration.parameters {
+            self.names.insert(parameter.name.clone());
+        }
+        self.visit_expression(&declaration.body);
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.names.insert(declaration.name.clone());
+        self.visit_expression(&declaration.initializer);
+    }
+
+    fn visit_number(&mut self, _number: &ASTNumberExpression) {}
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        self.names.insert(variable.name.clone());
+    }
+}
+
+// Rewrites one statement. Children are rewritten before their parent, so the
+// temporaries a hoisted subtree uses are declared ahead of it, and a repeated
+// subtree is looked up by its rewritten form.
+struct Hoister<'a> {
+    repeated: HashSet<ASTExpression>,
+    hoisted: HashMap<ASTExpression, String>,
+    temporaries: &'a mut Temporaries,
+    declarations: Vec<ASTStatement>,
+}
+
+impl ASTMutVisitor for Hoister<'_> {
+    fn visit_expression_mut(&mut self, expression: &mut ASTExpression) {
+        let shared = self.repeated.contains(&*expression);
+        self.do_visit_expression_mut(expression);
+        if !shared {
+            return;
+        }
+        let name = match self.hoisted.get(&*expression) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self.temporaries.fresh();
+                self.hoisted.insert(expression.clone(), name.clone());
+                self.declarations.push(ASTStatement::variable_declaration(
+                    name.clone(),
+                    TextSpan::new(0, name.len(), name.clone()),
+                    expression.clone(),
+                ));
+                name
+            }
+        };
+        let span = TextSpan::new(0, name.len(), name.clone());
+        let mut variable = ASTExpression::variable(name, span);
+        variable.span = expression.span;
+        *expression = variable;
+    }
+
+    fn visit_conditional_expression_mut(&mut self, conditional: &mut ASTConditionalExpression) {
+        self.visit_expression_mut(&mut conditional.condition);
+    }
+
+    fn visit_start_end_expression_mut(&mut self, _block: &mut ASTStartEndExpression) {}
+}
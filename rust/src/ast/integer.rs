@@ -0,0 +1,359 @@
+use crate::ast::{
+    ASTAbsoluteExpression, ASTBinaryExpression, ASTBinaryOperatorKind, ASTCallExpression,
+    ASTConditionalExpression, ASTFactorialExpression, ASTFunctionDeclaration, ASTNumberExpression,
+    ASTStartEndExpression, ASTUnaryExpression, ASTUnaryOperatorKind, ASTVariableDeclaration,
+    ASTVariableExpression, ASTVisitor, Ast, EvalError,
+};
+use crate::prelude::*;
+use alloc::rc::Rc;
+
+// What `+`, `-`, `*`, `^`, `|x|`, `!` and negation do when the result does not fit in
+// an i64.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum IntOverflow {
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+// Evaluates a program over i64 instead of f64. Literals must be whole numbers,
+// division floors, and comparisons produce 1 or 0.
+pub struct IntEvaluator {
+    pub last_value: Option<i64>,
+    pub variables: HashMap<String, i64>,
+    pub functions: HashMap<String, Rc<ASTFunctionDeclaration>>,
+    pub error: Option<EvalError>,
+    pub overflow: IntOverflow,
+    pub max_call_depth: usize,
+    call_depth: usize,
+    // Names declared by each enclosing block, innermost last. An outer
+    // binding they do not shadow keeps what the block assigned to it.
+    block_declarations: Vec<HashSet<String>>,
+}
+
+impl IntEvaluator {
+    pub fn new(overflow: IntOverflow) -> Self {
+        Self {
+            last_value: None,
+            variables: HashMap::new(),
+            functions: HashMap::new(),
+            error: None,
+            overflow,
+            max_call_depth: 128,
+            call_depth: 0,
+            block_declarations: Vec::new(),
+        }
+    }
+
+    pub fn evaluate(&mut self, ast: &Ast) -> Result<i64, EvalError> {
+        self.last_value = None;
+        self.error = None;
+        self.call_depth = 0;
+        for statement in &ast.statements {
+            self.visit_statement(statement);
+            if self.error.is_some() {
+                break;
+            }
+        }
+        match (&self.error, self.last_value) {
+            (Some(error), _) => Err(error.clone()),
+            (None, Some(value)) => Ok(value),
+            (None, None) => Err(EvalError::EmptyProgram),
+        }
+    }
+
+    fn fail(&mut self, error: EvalError) {
+        self.last_value = None;
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    // Applies the overflow policy to one operation, given its checked,
+    // wrapping and saturating forms.
+    fn apply(
+        &mut self,
+        checked: Option<i64>,
+        wrapping: impl FnOnce() -> i64,
+        saturating: impl FnOnce() -> i64,
+    ) -> Option<i64> {
+        match self.overflow {
+            IntOverflow::Checked => {
+                if checked.is_none() {
+                    self.fail(EvalError::Overflow);
+                }
+                checked
+            }
+            IntOverflow::Wrapping => Some(wrapping()),
+            IntOverflow::Saturating => Some(saturating()),
+        }
+    }
+
+    fn floor_divide(left: i64, right: i64) -> i64 {
+        let quotient = left.wrapping_div(right);
+        if left.wrapping_rem(right) != 0 && (left < 0) != (right < 0) {
+            quotient - 1
+        } else {
+            quotient
+        }
+    }
+
+    // With a negative exponent only bases of 1 and -1 have a whole result;
+    // anything else is reported rather than truncated.
+    fn power(&mut self, base: i64, exponent: i64) -> Option<i64> {
+        if exponent < 0 {
+            match base {
+                1 => return Some(1),
+                -1 if exponent % 2 == 0 => return Some(1),
+                -1 => return Some(-1),
+                0 => self.fail(EvalError::DivisionByZero),
+                _ => self.fail(EvalError::NonIntegerResult),
+            }
+            return None;
+        }
+        let exponent = u32::try_from(exponent).unwrap_or(u32::MAX);
+        self.apply(
+            base.checked_pow(exponent),
+            || base.wrapping_pow(exponent),
+            || base.saturating_pow(exponent),
+        )
+    }
+}
+
+impl Default for IntEvaluator {
+    fn default() -> Self {
+        Self::new(IntOverflow::default())
+    }
+}
+
+impl ASTVisitor for IntEvaluator {
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.visit_expression(&declaration.initializer);
+        let Some(value) = self.last_value else {
+            return;
+        };
+        let updates = declaration.assignment && self.variables.contains_key(&declaration.name);
+        if !updates {
+            if let Some(declared) = self.block_declarations.last_mut() {
+                declared.insert(declaration.name.clone());
+            }
+        }
+        self.variables.insert(declaration.name.clone(), value);
+    }
+
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.functions
+            .insert(declaration.name.clone(), Rc::new(declaration.clone()));
+    }
+
+    fn visit_number(&mut self, number: &ASTNumberExpression) {
+        let value = number.number;
+        // A percentage is a fraction, whatever the digits.
+        if number.is_percent() || value.fract() != 0.0 || value.abs() >= i64::MAX as f64 {
+            self.fail(EvalError::NonIntegerOperand);
+            return;
+        }
+        self.last_value = Some(value as i64);
+    }
+
+    // Comparisons give 1 or 0 here, so literals do the same.
+    fn visit_boolean(&mut self, value: bool) {
+        self.last_value = Some(value as i64);
+    }
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        match self.variables.get(&variable.name) {
+            Some(value) => self.last_value = Some(*value),
+            None => self.fail(EvalError::UndefinedVariable {
+                name: variable.name.clone(),
+                span: variable.span.clone(),
+            }),
+        }
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.visit_expression(&absolute_expression.expression);
+        let Some(value) = self.last_value else {
+            return;
+        };
+        self.last_value = self.apply(
+            value.checked_abs(),
+            || value.wrapping_abs(),
+            || value.saturating_abs(),
+        );
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.visit_expression(&unary_expression.operand);
+        let Some(value) = self.last_value else {
+            return;
+        };
+        self.last_value = match unary_expression.operator {
+            ASTUnaryOperatorKind::Neg => self.apply(
+                value.checked_neg(),
+                || value.wrapping_neg(),
+                || value.saturating_neg(),
+            ),
+        };
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+        let Some(value) = self.last_value else {
+            return;
+        };
+        if value < 0 {
+            self.fail(EvalError::NonIntegerOperand);
+            return;
+        }
+        let mut product: i64 = 1;
+        for factor in 2..=value {
+            let Some(next) = self.apply(
+                product.checked_mul(factor),
+                || product.wrapping_mul(factor),
+                || product.saturating_mul(factor),
+            ) else {
+                return;
+            };
+            product = next;
+            // Wrapping reaches 0 from 66! on and saturating sticks at the
+            // maximum, so further factors cannot change the result.
+            if product == 0 || product == i64::MAX {
+                break;
+            }
+        }
+        self.last_value = Some(product);
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.visit_expression(&conditional.condition);
+        let Some(condition) = self.last_value else {
+            return;
+        };
+        if condition != 0 {
+            self.visit_expression(&conditional.then_branch);
+        } else {
+            self.visit_expression(&conditional.else_branch);
+        }
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        let Some(function) = self.functions.get(&call.name).cloned() else {
+            self.fail(EvalError::UndefinedFunction(call.name.clone()));
+            return;
+        };
+        if function.parameters.len() != call.arguments.len() {
+            self.fail(EvalError::ArityMismatch {
+                name: call.name.clone(),
+                expected: function.parameters.len(),
+                found: call.arguments.len(),
+            });
+            return;
+        }
+        if self.call_depth >= self.max_call_depth {
+            self.fail(EvalError::RecursionLimit);
+            return;
+        }
+
+        // Parameters shadow the caller's bindings for the duration of the
+        // body only.
+        let mut scope = self.variables.clone();
+        for (parameter, argument) in function.parameters.iter().zip(&call.arguments) {
+            self.visit_expression(argument);
+            let Some(value) = self.last_value else {
+                return;
+            };
+            scope.insert(parameter.name.clone(), value);
+        }
+        let caller_scope = core::mem::replace(&mut self.variables, scope);
+        self.call_depth += 1;
+        self.visit_expression(&function.body);
+        self.call_depth -= 1;
+        self.variables = caller_scope;
+    }
+
+    // Declarations inside the block are dropped again when it ends, while
+    // assignments to outer variables stay.
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        let mut outer = self.variables.clone();
+        self.block_declarations.push(HashSet::new());
+        self.last_value = None;
+        for statement in &start_end_expression.statements {
+            self.visit_statement(statement);
+            if self.error.is_some() {
+                break;
+            }
+        }
+        let declared = self.block_declarations.pop().unwrap_or_default();
+        for (name, value) in outer.iter_mut() {
+            if !declared.contains(name) {
+                if let Some(assigned) = self.variables.get(name) {
+                    *value = *assigned;
+                }
+            }
+        }
+        self.variables = outer;
+        if self.last_value.is_none() && self.error.is_none() {
+            self.fail(EvalError::EmptyBlock);
+        }
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.visit_expression(&binary_expression.left);
+        let Some(left) = self.last_value else {
+            return;
+        };
+        self.visit_expression(&binary_expression.right);
+        let Some(right) = self.last_value else {
+            return;
+        };
+
+        let result = match binary_expression.operator.kind {
+            ASTBinaryOperatorKind::Plus => self.apply(
+                left.checked_add(right),
+                || left.wrapping_add(right),
+                || left.saturating_add(right),
+            ),
+            ASTBinaryOperatorKind::Minus => self.apply(
+                left.checked_sub(right),
+                || left.wrapping_sub(right),
+                || left.saturating_sub(right),
+            ),
+            ASTBinaryOperatorKind::Multiply => self.apply(
+                left.checked_mul(right),
+                || left.wrapping_mul(right),
+                || left.saturating_mul(right),
+            ),
+            ASTBinaryOperatorKind::Divide | ASTBinaryOperatorKind::IntegerDivide => {
+                if right == 0 {
+                    self.fail(EvalError::DivisionByZero);
+                    return;
+                }
+                // Only `i64::MIN / -1` overflows here.
+                self.apply(
+                    left.checked_div(right)
+                        .map(|_| Self::floor_divide(left, right)),
+                    || Self::floor_divide(left, right),
+                    || left.saturating_div(right),
+                )
+            }
+            ASTBinaryOperatorKind::Pow => self.power(left, right),
+            ASTBinaryOperatorKind::BitXor => Some(left ^ right),
+            ASTBinaryOperatorKind::At => {
+                let symbol = binary_expression.operator.kind.to_string();
+                self.fail(EvalError::UnregisteredOperator(symbol));
+                return;
+            }
+            ASTBinaryOperatorKind::Less => Some((left < right) as i64),
+            ASTBinaryOperatorKind::LessEqual => Some((left <= right) as i64),
+            ASTBinaryOperatorKind::Greater => Some((left > right) as i64),
+            ASTBinaryOperatorKind::GreaterEqual => Some((left >= right) as i64),
+            ASTBinaryOperatorKind::Equal => Some((left == right) as i64),
+            ASTBinaryOperatorKind::NotEqual => Some((left != right) as i64),
+        };
+        if result.is_some() {
+            self.last_value = result;
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use crate::ast::units::UnitChecker;
+use crate::ast::{
+    ASTBinaryExpression, ASTBinaryOperatorKind, ASTCallExpression, ASTConditionalExpression,
+    ASTEvaluator, ASTExpression, ASTExpressionKind, ASTFunctionDeclaration, ASTStatement,
+    ASTStatementKind, ASTUnaryOperatorKind, ASTVariableDeclaration, ASTVisitor, Ast, EvalError,
+    Number, Value,
+};
+use crate::prelude::*;
+use alloc::rc::Rc;
+
+// What is left to do for a node whose operands are still being evaluated.
+// Steps run in last-in, first-out order, so a node pushes its own
+// continuation before the operand it waits for.
+enum Step<'a, N: Number> {
+    Evaluate(&'a ASTExpression),
+    // Only scheduled with `error_source` set.
+    Finished {
+        expression: &'a ASTExpression,
+        had_error: bool,
+    },
+    Absolute,
+    Unary(&'a ASTUnaryOperatorKind),
+    Factorial,
+    Branch(&'a ASTConditionalExpression),
+    Right(&'a ASTBinaryExpression),
+    Binary {
+        operator: &'a ASTBinaryOperatorKind,
+        left: Option<N>,
+        left_error: Option<EvalError>,
+        percent_change: bool,
+    },
+    Argument {
+        call: &'a ASTCallExpression,
+        function: Rc<ASTFunctionDeclaration>,
+        index: usize,
+        scope: HashMap<String, Value<N>>,
+    },
+    BuiltinArgument {
+        call: &'a ASTCallExpression,
+        arguments: Vec<N>,
+    },
+    // The statements of a block still to run, stopping at the first error.
+    Statements(&'a [ASTStatement]),
+    EndBlock,
+    Declare(&'a ASTVariableDeclaration),
+    // `Print` evaluates the first argument; `Printed` records it and moves
+    // on to the rest.
+    Print(&'a [ASTExpression]),
+    Printed(&'a [ASTExpression]),
+}
+
+impl<N: Number> ASTEvaluator<'_, N> {
+    // Same results as `evaluate_value`, but expressions are walked with an
+    // explicit stack, so a long chain such as `1+1+...+1` cannot overflow
+    // the native one. Only function calls still nest, and those are bounded
+    // by `max_call_depth`.
+    pub fn evaluate_iterative(&mut self, ast: &Ast) -> Result<Value<N>, EvalError> {
+        UnitChecker::check(ast)?;
+        self.last_value = None;
+        self.error = None;
+        self.call_depth = 0;
+        for statement in &ast.statements {
+            if self.propagate_errors {
+                self.last_value = None;
+                self.error = None;
+            }
+            let mut steps = Vec::new();
+            self.schedule(statement, &mut steps);
+            self.run_steps(steps);
+            self.bind_answer();
+            if self.error.is_some() && !self.propagate_errors {
+                break;
+            }
+        }
+        self.value_result()
+    }
+
+    fn run(&mut self, root: &ASTExpression) {
+        self.run_steps(vec![Step::Evaluate(root)]);
+    }
+
+    fn run_steps<'a>(&mut self, mut steps: Vec<Step<'a, N>>) {
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Evaluate(expression) => {
+                    if self.error_source {
+                        steps.push(Step::Finished {
+                            expression,
+                            had_error: self.error.is_some(),
+                        });
+                    }
+                    self.start(expression, &mut steps)
+                }
+                Step::Finished {
+                    expression,
+                    had_error,
+                } => self.attach_source(expression, had_error),
+                Step::Absolute => self.finish_absolute(),
+                Step::Unary(operator) => self.finish_unary(operator),
+                Step::Factorial => self.finish_factorial(),
+                Step::Branch(conditional) => match self.condition() {
+                    Some(true) => steps.push(Step::Evaluate(&conditional.then_branch)),
+                    Some(false) => steps.push(Step::Evaluate(&conditional.else_branch)),
+                    None => {}
+                },
+                Step::Right(binary_expression) => {
+                    let left = self.operand();
+                    if left.is_none() && !self.propagate_errors {
+                        continue;
+                    }
+                    let left_error = self.error.take();
+                    steps.push(Step::Binary {
+                        operator: &binary_expression.operator.kind,
+                        left,
+                        left_error,
+                        percent_change: binary_expression.is_percent_change(),
+                    });
+                    steps.push(Step::Evaluate(&binary_expression.right));
+                }
+                Step::Binary {
+                    operator,
+                    left,
+                    left_error,
+                    percent_change,
+                } => {
+                    if percent_change {
+                        self.scale_percent(&left);
+                    }
+                    self.finish_binary(operator, left, left_error)
+                }
+                Step::Argument {
+                    call,
+                    function,
+                    index,
+                    mut scope,
+                } => {
+                    let Some(value) = self.last_value.clone() else {
+                        continue;
+                    };
+                    scope.insert(function.parameters[index].name.clone(), value);
+                    self.next_argument(call, function, index + 1, scope, &mut steps);
+                }
+                Step::BuiltinArgument {
+                    call,
+                    mut arguments,
+                } => {
+                    let Some(value) = self.operand() else {
+                        continue;
+                    };
+                    arguments.push(value);
+                    self.next_builtin_argument(call, arguments, &mut steps);
+                }
+                Step::Statements(statements) => {
+                    let Some((statement, rest)) = statements.split_first() else {
+                        continue;
+                    };
+                    if self.error.is_some() {
+                        continue;
+                    }
+                    steps.push(Step::Statements(rest));
+                    self.schedule(statement, &mut steps);
+                }
+                Step::EndBlock => self.leave_block(),
+                Step::Declare(declaration) => self.finish_variable_declaration(declaration),
+                Step::Print(arguments) => {
+                    if let Some((argument, rest)) = arguments.split_first() {
+                        steps.push(Step::Printed(rest));
+                        steps.push(Step::Evaluate(argument));
+                    }
+                }
+                Step::Printed(rest) => {
+                    if self.finish_print() {
+                        steps.push(Step::Print(rest));
+                    }
+                }
+            }
+        }
+    }
+
+    // Queues the work for one statement, at the top level or in a block.
+    fn schedule<'a>(&mut self, statement: &'a ASTStatement, steps: &mut Vec<Step<'a, N>>) {
+        match &statement.kind {
+            ASTStatementKind::Expression(expression) => steps.push(Step::Evaluate(expression)),
+            ASTStatementKind::VariableDeclaration(declaration) => {
+                steps.push(Step::Declare(declaration));
+                steps.push(Step::Evaluate(&declaration.initializer));
+            }
+            ASTStatementKind::FunctionDeclaration(declaration) => {
+                self.visit_function_declaration(declaration)
+            }
+            ASTStatementKind::Print(arguments) => steps.push(Step::Print(arguments)),
+        }
+    }
+
+    // Evaluates leaves on the spot and schedules the children of everything
+    // else.
+    fn start<'a>(&mut self, expression: &'a ASTExpression, steps: &mut Vec<Step<'a, N>>) {
+        match &expression.kind {
+            ASTExpressionKind::Number(number) => self.visit_number(number),
+            ASTExpressionKind::Boolean(value) => self.visit_boolean(*value),
+            ASTExpressionKind::Variable(variable) => self.visit_variable(variable),
+            ASTExpressionKind::Parenthesized(parenthesized) => {
+                steps.push(Step::Evaluate(&parenthesized.expression))
+            }
+            ASTExpressionKind::StartEnd(block) => {
+                self.enter_block();
+                steps.push(Step::EndBlock);
+                steps.push(Step::Statements(&block.statements));
+            }
+            ASTExpressionKind::Absolute(absolute) => {
+                steps.push(Step::Absolute);
+                steps.push(Step::Evaluate(&absolute.expression));
+            }
+            ASTExpressionKind::Unary(unary) => {
+                steps.push(Step::Unary(&unary.operator));
+                steps.push(Step::Evaluate(&unary.operand));
+            }
+            ASTExpressionKind::Factorial(factorial) => {
+                steps.push(Step::Factorial);
+                steps.push(Step::Evaluate(&factorial.expression));
+            }
+            ASTExpressionKind::Conditional(conditional) => {
+                steps.push(Step::Branch(conditional));
+                steps.push(Step::Evaluate(&conditional.condition));
+            }
+            ASTExpressionKind::Binary(binary_expression) => {
+                steps.push(Step::Right(binary_expression));
+                steps.push(Step::Evaluate(&binary_expression.left));
+            }
+            ASTExpressionKind::Call(call) if self.is_builtin(&call.name) => {
+                self.next_builtin_argument(call, Vec::new(), steps)
+            }
+            ASTExpressionKind::Call(call) => {
+                if let Some(function) = self.callee(call) {
+                    self.next_argument(call, function, 0, HashMap::new(), steps);
+                }
+            }
+        }
+    }
+
+    // Schedules argument `index`, or runs the body once every argument is
+    // bound. The body gets a stack of its own, so call nesting is the only
+    // recursion left.
+    fn next_argument<'a>(
+        &mut self,
+        call: &'a ASTCallExpression,
+        function: Rc<ASTFunctionDeclaration>,
+        index: usize,
+        scope: HashMap<String, Value<N>>,
+        steps: &mut Vec<Step<'a, N>>,
+    ) {
+        let Some(argument) = call.arguments.get(index) else {
+            let caller_scopes = self.enter_call(scope);
+            self.run(&function.body);
+            self.leave_call(caller_scopes);
+            return;
+        };
+        steps.push(Step::Argument {
+            call,
+            function,
+            index,
+            scope,
+        });
+        steps.push(Step::Evaluate(argument));
+    }
+
+    fn next_builtin_argument<'a>(
+        &mut self,
+        call: &'a ASTCallExpression,
+        arguments: Vec<N>,
+        steps: &mut Vec<Step<'a, N>>,
+    ) {
+        let Some(argument) = call.arguments.get(arguments.len()) else {
+            self.finish_builtin(call, arguments);
+            return;
+        };
+        steps.push(Step::BuiltinArgument { call, arguments });
+        steps.push(Step::Evaluate(argument));
+    }
+}
@@ -1,28 +1,153 @@
+use crate::prelude::*;
+use core::fmt;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenKind {
-    Real(f64),
+    // The value and, when the lexer reads units, a unit such as `m` in `3m`.
+    Real(f64, Option<String>),
+    // `true` or `false`.
+    Boolean(bool),
     Variable(String),
     To,
     Assing,
+    Var,
     Define,
     For,
+    If,
+    Then,
+    Else,
     Term,
     Begin,
     End,
     Print,
     Pow,
+    Xor,
     Plus,
     Minus,
     Asterisk,
     Slash,
     IntegerDivide,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    EqualEqual,
+    BangEqual,
+    Bang,
+    At,
+    Percent,
     LeftParen,
     RightParen,
+    Bar,
+    Comma,
     Bad,
     Whitespace,
+    Comment,
+    // `## text` up to the end of the line, with the text trimmed. Unlike
+    // other comments it is always emitted, for the parser to attach to the
+    // statement that follows.
+    DocComment(String),
     Eof,
 }
 
+impl TokenKind {
+    // The variant name without any payload, so `Real(1.0)` and `Real(2.0)`
+    // fall into the same bucket.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenKind::Real(..) => "Real",
+            TokenKind::Boolean(_) => "Boolean",
+            TokenKind::Variable(_) => "Variable",
+            TokenKind::To => "To",
+            TokenKind::Assing => "Assing",
+            TokenKind::Var => "Var",
+            TokenKind::Define => "Define",
+            TokenKind::For => "For",
+            TokenKind::If => "If",
+            TokenKind::Then => "Then",
+            TokenKind::Else => "Else",
+            TokenKind::Term => "Term",
+            TokenKind::Begin => "Begin",
+            TokenKind::End => "End",
+            TokenKind::Print => "Print",
+            TokenKind::Pow => "Pow",
+            TokenKind::Xor => "Xor",
+            TokenKind::Plus => "Plus",
+            TokenKind::Minus => "Minus",
+            TokenKind::Asterisk => "Asterisk",
+            TokenKind::Slash => "Slash",
+            TokenKind::IntegerDivide => "IntegerDivide",
+            TokenKind::Less => "Less",
+            TokenKind::LessEqual => "LessEqual",
+            TokenKind::Greater => "Greater",
+            TokenKind::GreaterEqual => "GreaterEqual",
+            TokenKind::EqualEqual => "EqualEqual",
+            TokenKind::BangEqual => "BangEqual",
+            TokenKind::Bang => "Bang",
+            TokenKind::At => "At",
+            TokenKind::Percent => "Percent",
+            TokenKind::LeftParen => "LeftParen",
+            TokenKind::RightParen => "RightParen",
+            TokenKind::Bar => "Bar",
+            TokenKind::Comma => "Comma",
+            TokenKind::Bad => "Bad",
+            TokenKind::Whitespace => "Whitespace",
+            TokenKind::Comment => "Comment",
+            TokenKind::DocComment(_) => "DocComment",
+            TokenKind::Eof => "Eof",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LexError {
+    BadCharacter(TextSpan),
+    UnterminatedComment(TextSpan),
+    UnterminatedIdentifier(TextSpan),
+    // Spans the first `max_identifier_len` characters of the identifier.
+    IdentifierTooLong(TextSpan),
+    DanglingDecimalPoint(TextSpan),
+    // Digits and decimal points that do not form a number, such as `1.2.3`.
+    MalformedNumber(TextSpan),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::BadCharacter(span) => {
+                write!(
+                    f,
+                    "unexpected character `{}` at {}",
+                    span.literal, span.start
+                )
+            }
+            LexError::UnterminatedComment(span) => {
+                write!(f, "unterminated comment starting at {}", span.start)
+            }
+            LexError::UnterminatedIdentifier(span) => {
+                write!(
+                    f,
+                    "unterminated quoted identifier starting at {}",
+                    span.start
+                )
+            }
+            LexError::IdentifierTooLong(span) => {
+                write!(f, "identifier starting at {} is too long", span.start)
+            }
+            LexError::DanglingDecimalPoint(span) => write!(
+                f,
+                "`{}` at {} has no digits after the decimal point",
+                span.literal, span.start
+            ),
+            LexError::MalformedNumber(span) => {
+                write!(f, "malformed number `{}` at {}", span.literal, span.start)
+            }
+        }
+    }
+}
+
+impl core::error::Error for LexError {}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct TextSpan {
     pub(crate) start: usize,
@@ -59,6 +184,32 @@ impl Token {
 pub struct Lexer<'a> {
     input: &'a str,
     current_pos: usize,
+    errors: Vec<LexError>,
+    // A token lexed by `peek_token` and not yet handed out; the inner `None`
+    // records that the input was exhausted.
+    peeked: Option<Option<Token>>,
+    // Match keywords such as `FOR` or `Print` regardless of case. Variable
+    // names keep their original spelling either way.
+    pub case_insensitive_keywords: bool,
+    // Character accepted as the decimal point inside numbers. Setting it to
+    // `,` makes `3,14` a single number, so commas directly following a digit
+    // can no longer separate call arguments; pick one convention per input.
+    pub decimal_separator: char,
+    // Lex `^` as bitwise XOR, as in C. The two meanings are mutually
+    // exclusive: with this set, `**` is the only way to write a power.
+    pub caret_is_xor: bool,
+    // Emit block comments as `Comment` tokens instead of dropping them, for
+    // tools such as formatters. The parser skips them either way.
+    pub preserve_comments: bool,
+    // Longest identifier accepted, in characters. Anything longer is lexed
+    // as a single `Bad` token spanning only the accepted prefix, and
+    // reported, without buffering the excess.
+    pub max_identifier_len: usize,
+    // Read letters directly after a number as its unit, so `3m` is three
+    // metres rather than `3` followed by the variable `m`.
+    pub units: bool,
+    // Columns a tab advances by in `line_column`.
+    pub tab_width: usize,
 }
 
 impl<'a> Lexer<'a> {
@@ -66,123 +217,337 @@ impl<'a> Lexer<'a> {
         Self {
             input,
             current_pos: 0,
+            errors: Vec::new(),
+            peeked: None,
+            case_insensitive_keywords: false,
+            decimal_separator: '.',
+            caret_is_xor: false,
+            preserve_comments: false,
+            max_identifier_len: 256,
+            units: false,
+            tab_width: 4,
+        }
+    }
+
+    // The 1-based line and column of `offset` in the input, for diagnostics.
+    // `\r\n` and a lone `\r` each end a line just like `\n` does.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let mut line = 1;
+        let mut column = 1;
+        let mut chars = self.input.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            if index >= offset {
+                break;
+            }
+            match c {
+                '\r' if chars.peek().is_some_and(|(_, next)| *next == '\n') => {}
+                '\n' | '\r' => {
+                    line += 1;
+                    column = 1;
+                }
+                '\t' => column += self.tab_width,
+                _ => column += 1,
+            }
+        }
+        (line, column)
+    }
+
+    // Lexes the whole input, dropping whitespace and the trailing `Eof`
+    // marker, and returns the tokens together with every error hit along the
+    // way. The parser treats running out of tokens as the end of input.
+    pub fn tokenize(input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            if !matches!(token.kind, TokenKind::Whitespace | TokenKind::Eof) {
+                tokens.push(token);
+            }
+        }
+        (tokens, lexer.errors)
+    }
+
+    // Counts the tokens of `input` by kind, ignoring whitespace.
+    pub fn token_histogram(input: &str) -> HashMap<&'static str, usize> {
+        let mut histogram = HashMap::new();
+        for token in Self::tokenize(input).0 {
+            *histogram.entry(token.kind.name()).or_insert(0) += 1;
         }
+        histogram
+    }
+
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
     }
 
     pub fn next_token(&mut self) -> Option<Token> {
+        match self.peeked.take() {
+            Some(token) => token,
+            None => self.lex_token(),
+        }
+    }
+
+    // Returns the token `next_token` will return next without consuming it.
+    // Errors are recorded as soon as the token is lexed.
+    pub fn peek_token(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex_token());
+        }
+        self.peeked.as_ref().and_then(Option::as_ref)
+    }
+
+    fn lex_token(&mut self) -> Option<Token> {
         if self.current_pos == self.input.len() {
             let eof_char: char = '\0';
+            let eof_pos = self.current_pos;
             self.current_pos += 1;
             return Some(Token::new(
                 TokenKind::Eof,
-                TextSpan::new(0, 0, eof_char.to_string()),
+                TextSpan::new(eof_pos, eof_pos, eof_char.to_string()),
             ));
         }
-        let c = self.current_char();
-        return c.map(|c| {
+        let token = self.current_char().map(|c| {
             let start = self.current_pos;
-            let mut kind = TokenKind::Bad;
+            // Where the token's span ends, if not at the current position.
+            let mut truncated_end = None;
+            let kind;
             if Self::is_number_start(&c) {
-                let number: f64 = self.consume_number();
-                kind = TokenKind::Real(number);
+                kind = self.consume_number();
             } else if Self::is_whitespace(&c) {
                 self.consume();
                 kind = TokenKind::Whitespace;
             } else if c.is_alphabetic() {
                 let mut variable_name = String::new();
+                let mut length = 0;
+                let mut kept_end = start;
                 while let Some(c) = self.current_char() {
                     if c.is_alphanumeric() {
                         self.consume().unwrap();
-                        variable_name.push(c);
+                        if length < self.max_identifier_len {
+                            variable_name.push(c);
+                            kept_end = self.current_pos;
+                        }
+                        length += 1;
                     } else {
                         break;
                     }
                 }
-                match variable_name.as_str() {
-                    "for" => kind = TokenKind::For,
-                    "print" => kind = TokenKind::Print,
-                    "var" => kind = TokenKind::Define,
-                    _ => kind = TokenKind::Variable(variable_name),
-                }
+                kind = if length > self.max_identifier_len {
+                    truncated_end = Some(kept_end);
+                    self.errors.push(LexError::IdentifierTooLong(TextSpan::new(
+                        start,
+                        kept_end,
+                        variable_name,
+                    )));
+                    TokenKind::Bad
+                } else {
+                    self.keyword(&variable_name)
+                        .unwrap_or(TokenKind::Variable(variable_name))
+                };
             } else {
                 kind = self.consume_punctuation();
             }
 
-            let end = self.current_pos;
+            let end = truncated_end.unwrap_or(self.current_pos);
             let literal = self.input[start..end].to_string();
             let span = TextSpan::new(start, end, literal);
             Token::new(kind, span)
-        });
+        })?;
+        if token.kind == TokenKind::Comment && !self.preserve_comments {
+            return self.lex_token();
+        }
+        Some(token)
+    }
+
+    fn keyword(&self, name: &str) -> Option<TokenKind> {
+        let name = if self.case_insensitive_keywords {
+            name.to_lowercase()
+        } else {
+            name.to_string()
+        };
+        match name.as_str() {
+            "for" => Some(TokenKind::For),
+            "print" => Some(TokenKind::Print),
+            "var" => Some(TokenKind::Var),
+            "def" => Some(TokenKind::Define),
+            "if" => Some(TokenKind::If),
+            "then" => Some(TokenKind::Then),
+            "else" => Some(TokenKind::Else),
+            "true" => Some(TokenKind::Boolean(true)),
+            "false" => Some(TokenKind::Boolean(false)),
+            _ => None,
+        }
     }
 
     fn consume_punctuation(&mut self) -> TokenKind {
+        let two_char = self
+            .try_two_char('*', '*', TokenKind::Pow)
+            .or_else(|| self.try_two_char('=', '=', TokenKind::EqualEqual))
+            .or_else(|| self.try_two_char('<', '=', TokenKind::LessEqual))
+            .or_else(|| self.try_two_char('>', '=', TokenKind::GreaterEqual))
+            .or_else(|| self.try_two_char('!', '=', TokenKind::BangEqual))
+            .or_else(|| self.try_two_char('/', '/', TokenKind::IntegerDivide));
+        if let Some(kind) = two_char {
+            return kind;
+        }
         let c = self.consume().unwrap();
         match c {
             '+' => TokenKind::Plus,
             '-' => TokenKind::Minus,
             '*' => TokenKind::Asterisk,
             '=' => TokenKind::Assing,
+            '<' => TokenKind::Less,
+            '>' => TokenKind::Greater,
+            '!' => TokenKind::Bang,
             ';' => TokenKind::Term,
-            '/' => {
-                if let Some(next_char) = self.current_char() {
-                    if next_char == '/' {
-                        self.consume();
-                        TokenKind::IntegerDivide
-                    } else {
-                        TokenKind::Slash
-                    }
-                } else {
-                    TokenKind::Slash
-                }
-            }
+            '/' if self.consume_if('*') => self.consume_block_comment(),
+            '/' => TokenKind::Slash,
             '(' => TokenKind::LeftParen,
             ')' => TokenKind::RightParen,
             '{' => TokenKind::Begin,
             '}' => TokenKind::End,
+            '^' if self.caret_is_xor => TokenKind::Xor,
             '^' => TokenKind::Pow,
-            _ => TokenKind::Bad,
+            '|' => TokenKind::Bar,
+            ',' => TokenKind::Comma,
+            '@' => TokenKind::At,
+            '`' => self.consume_quoted_identifier(),
+            '%' => TokenKind::Percent,
+            '#' if self.consume_if('#') => self.consume_doc_comment(),
+            _ => {
+                let start = self.current_pos - c.len_utf8();
+                let span = TextSpan::new(start, self.current_pos, c.to_string());
+                self.errors.push(LexError::BadCharacter(span));
+                TokenKind::Bad
+            }
+        }
+    }
+
+    // Consumes `first` and `second` together as `kind`. Every two-character
+    // operator goes through here before the single characters are matched,
+    // so none of them can be lexed as its one-character sibling by mistake.
+    fn try_two_char(&mut self, first: char, second: char, kind: TokenKind) -> Option<TokenKind> {
+        let mut rest = self.input.get(self.current_pos..)?.chars();
+        if rest.next() != Some(first) || rest.next() != Some(second) {
+            return None;
+        }
+        self.consume();
+        self.consume();
+        Some(kind)
+    }
+
+    // Consumes the second character of a comment opener such as `/*`. The
+    // token span is taken from the start of the first character, so it
+    // covers both.
+    fn consume_if(&mut self, expected: char) -> bool {
+        if self.current_char() == Some(expected) {
+            self.consume();
+            true
+        } else {
+            false
         }
     }
 
+    fn consume_block_comment(&mut self) -> TokenKind {
+        let start = self.current_pos - 2;
+        while let Some(c) = self.consume() {
+            if c == '*' && self.current_char() == Some('/') {
+                self.consume();
+                return TokenKind::Comment;
+            }
+        }
+        let end = self.current_pos;
+        let literal = self.input[start..end].to_string();
+        self.errors
+            .push(LexError::UnterminatedComment(TextSpan::new(
+                start, end, literal,
+            )));
+        TokenKind::Bad
+    }
+
+    // Everything up to the closing backtick is the name, spaces and all, and
+    // it is never a keyword.
+    fn consume_quoted_identifier(&mut self) -> TokenKind {
+        let start = self.current_pos - 1;
+        while let Some(c) = self.consume() {
+            if c == '`' {
+                let name = &self.input[start + 1..self.current_pos - 1];
+                return TokenKind::Variable(name.to_string());
+            }
+        }
+        let end = self.current_pos;
+        let literal = self.input[start..end].to_string();
+        self.errors
+            .push(LexError::UnterminatedIdentifier(TextSpan::new(
+                start, end, literal,
+            )));
+        TokenKind::Bad
+    }
+
+    // The line break is left for the next token.
+    fn consume_doc_comment(&mut self) -> TokenKind {
+        let start = self.current_pos;
+        while self.current_char().is_some_and(|c| c != '\n') {
+            self.consume();
+        }
+        TokenKind::DocComment(self.input[start..self.current_pos].trim().to_string())
+    }
+
     fn is_number_start(c: &char) -> bool {
-        c.is_digit(10)
+        c.is_ascii_digit()
     }
 
     fn is_whitespace(c: &char) -> bool {
         c.is_whitespace()
     }
 
+    // `current_pos` is a byte offset, so spans can slice `input` directly.
     fn current_char(&self) -> Option<char> {
-        self.input.chars().nth(self.current_pos)
+        self.input.get(self.current_pos..)?.chars().next()
     }
 
     fn consume(&mut self) -> Option<char> {
-        if self.current_pos >= self.input.len() {
-            return None;
-        }
-        let c = self.current_char();
-        self.current_pos += 1;
-
-        c
+        let c = self.current_char()?;
+        self.current_pos += c.len_utf8();
+        Some(c)
     }
-    fn consume_number(&mut self) -> f64 {
+    // A decimal point must be followed by a digit, so `1.` and the `1.` of
+    // `1. 5` are rejected rather than read as `1.0`.
+    fn consume_number(&mut self) -> TokenKind {
+        let start = self.current_pos;
         let mut number_str = String::new();
-        let mut is_whole_number = true;
         while let Some(c) = self.current_char() {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 self.consume().unwrap();
                 number_str.push(c);
-            } else if c == '.' {
+            } else if c == self.decimal_separator {
                 self.consume().unwrap();
-                number_str.push(c);
-                is_whole_number = false;
+                number_str.push('.');
             } else {
                 break;
             }
         }
-        if is_whole_number && !number_str.is_empty() {
-            number_str.push_str(".0");
+        let span = TextSpan::new(
+            start,
+            self.current_pos,
+            self.input[start..self.current_pos].to_string(),
+        );
+        if number_str.ends_with('.') {
+            self.errors.push(LexError::DanglingDecimalPoint(span));
+            return TokenKind::Bad;
+        }
+        // Only the separator is normalised; whole numbers parse as they are.
+        let Ok(number) = number_str.parse::<f64>() else {
+            self.errors.push(LexError::MalformedNumber(span));
+            return TokenKind::Bad;
+        };
+        if !self.units {
+            return TokenKind::Real(number, None);
+        }
+        let mut unit = String::new();
+        while let Some(c) = self.current_char().filter(|c| c.is_alphabetic()) {
+            self.consume().unwrap();
+            unit.push(c);
         }
-        number_str.parse::<f64>().unwrap_or(0.0)
+        TokenKind::Real(number, Some(unit).filter(|unit| !unit.is_empty()))
     }
 }
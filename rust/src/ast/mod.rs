@@ -1,11 +1,40 @@
-use crate::ast::lexer::Token;
+use crate::ast::analysis::{
+    LiteralDivisionByZeroFinder, OperationCounter, OperatorCollector, ParenDepthFinder,
+    PrecedenceExplainer, ShadowFinder,
+};
+use crate::ast::integer::{IntEvaluator, IntOverflow};
+use crate::ast::lexer::{Lexer, TextSpan, Token, TokenKind};
+use crate::ast::rpn::RpnToken;
+use crate::ast::units::UnitChecker;
+use crate::ast::validator::{ASTValidator, ValidationError};
+pub mod analysis;
+pub mod cse;
+pub mod integer;
+pub mod iterative;
 pub mod lexer;
 pub mod parser;
+pub mod reference;
+pub mod rpn;
+pub mod units;
+pub mod validator;
+
+use crate::prelude::*;
+use alloc::rc::Rc;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+#[cfg(feature = "std")]
+use std::io;
 
 pub struct Ast {
     pub statements: Vec<ASTStatement>,
 }
 
+impl Default for Ast {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Ast {
     pub fn new() -> Self {
         Self {
@@ -23,26 +52,242 @@ impl Ast {
         }
     }
 
-    pub fn visualize(&self) -> () {
-        let mut printer = ASTPrinter { indent: 1 };
-        self.visit(&mut printer);
+    pub fn visit_mut(&mut self, visitor: &mut dyn ASTMutVisitor) {
+        for statement in &mut self.statements {
+            visitor.visit_statement_mut(statement);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn visualize(&self) {
+        ASTPrinter::new()
+            .print_to(self, &mut IoWriter(io::stdout()))
+            .expect("failed to write to stdout");
+    }
+
+    pub fn visualize_string(&self) -> String {
+        ASTPrinter::new().render(self)
+    }
+
+    // Counted in characters rather than bytes, since the width is meant for
+    // laying the tree out in a terminal.
+    pub fn visualization_width(&self) -> usize {
+        self.visualize_string()
+            .lines()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[cfg(feature = "std")]
+    #[allow(non_snake_case)]
+    pub fn visualizeXML(&self) {
+        ASTXMLPrinter::new()
+            .print_to(self, &mut IoWriter(io::stdout()))
+            .expect("failed to write to stdout");
+    }
+
+    pub fn visualize_xml_string(&self) -> String {
+        ASTXMLPrinter::new().render(self)
+    }
+
+    pub fn to_source(&self) -> String {
+        ASTSourcePrinter::new().render(self)
+    }
+
+    // Only a program that is a single expression without conditionals or
+    // calls has a postfix form.
+    pub fn to_rpn(&self) -> Option<Vec<RpnToken>> {
+        match self.statements.as_slice() {
+            [ASTStatement {
+                kind: ASTStatementKind::Expression(expression),
+                ..
+            }] => rpn::to_rpn(expression),
+            _ => None,
+        }
+    }
+
+    // The innermost expression whose source span contains the byte
+    // `offset`, e.g. for what is under the cursor in an editor. Spans of
+    // siblings never overlap, so at most one child needs following.
+    pub fn node_at(&self, offset: usize) -> Option<&ASTExpression> {
+        let mut found = self
+            .statements
+            .iter()
+            .flat_map(ASTStatement::expressions)
+            .find(|expression| expression.contains(offset))?;
+        while let Some(child) = found
+            .children()
+            .into_iter()
+            .find(|child| child.contains(offset))
+        {
+            found = child;
+        }
+        Some(found)
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut validator = ASTValidator::new();
+        if self.statements.is_empty() {
+            validator.errors.push(ValidationError::EmptyProgram);
+        }
+        self.visit(&mut validator);
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(validator.errors)
+        }
+    }
+
+    pub fn has_literal_division_by_zero(&self) -> bool {
+        let mut finder = LiteralDivisionByZeroFinder::default();
+        self.visit(&mut finder);
+        finder.found
+    }
+
+    pub fn operation_count(&self) -> usize {
+        let mut counter = OperationCounter::default();
+        self.visit(&mut counter);
+        counter.count
+    }
+
+    pub fn max_paren_depth(&self) -> usize {
+        let mut finder = ParenDepthFinder::default();
+        self.visit(&mut finder);
+        finder.max_depth
+    }
+
+    pub fn operators_used(&self) -> HashSet<ASTBinaryOperatorKind> {
+        let mut collector = OperatorCollector::default();
+        self.visit(&mut collector);
+        collector.operators
+    }
+
+    // For teaching: which operator binds before which, one per line, e.g.
+    // "`*` (prec 4) binds before `+` (prec 3)" for `2 + 3 * 4`. Empty when
+    // no operator has another as an operand.
+    pub fn explain_precedence(&self) -> String {
+        let mut explainer = PrecedenceExplainer::default();
+        self.visit(&mut explainer);
+        explainer.lines.join("\n")
+    }
+
+    pub fn shadowed_variables(&self) -> Vec<(String, TextSpan)> {
+        let mut finder = ShadowFinder::default();
+        self.visit(&mut finder);
+        finder.shadowed
+    }
+
+    // Rewrites every use of the variable `from` to `to`, including `var`
+    // declarations. Function bodies with a parameter named `from` are left
+    // alone since the parameter shadows it there.
+    pub fn rename_variable(&mut self, from: &str, to: &str) {
+        self.visit_mut(&mut VariableRenamer { from, to });
+    }
+
+    // Puts the operands of `+` and `*` in a fixed order, so `3 + x` and
+    // `x + 3` give the same tree. Other operators are left alone.
+    pub fn canonicalize(&mut self) {
+        self.visit_mut(&mut Canonicalizer);
+    }
+
+    // A copy in which subtrees repeated within a statement are computed once
+    // into a temporary; see `cse::eliminate`.
+    pub fn cse(&self) -> Ast {
+        cse::eliminate(self)
+    }
+
+    // Structural equality where operators, names and tree shape must match
+    // exactly but number literals only need to be within `epsilon`.
+    pub fn approx_eq(&self, other: &Ast, epsilon: f64) -> bool {
+        self.statements.len() == other.statements.len()
+            && self
+                .statements
+                .iter()
+                .zip(&other.statements)
+                .all(|(left, right)| left.approx_eq(right, epsilon))
+    }
+
+    pub fn len(&self) -> usize {
+        self.statements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    // Fails with `EmptyProgram` when there are no statements.
+    pub fn evaluate(&self) -> Result<f64, EvalError> {
+        self.evaluate_with(HashMap::new())
     }
 
-    pub fn visualizeXML(&self) -> () {
-        print!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
-        print!("<program>\n");
-        let mut printer = ASTXMLPrinter { indent: 1 };
-        self.visit(&mut printer);
+    pub fn evaluate_value(&self) -> Result<Value, EvalError> {
+        ASTEvaluator::new().evaluate_value(self)
+    }
 
-        print!("</program>");
+    // For programs too deeply nested for the recursive evaluator.
+    pub fn evaluate_iterative(&self) -> Result<f64, EvalError> {
+        ASTEvaluator::new()
+            .evaluate_iterative(self)
+            .and_then(Value::into_number)
     }
 
-    pub fn evaluate(&self) -> Option<f64> {
+    pub fn evaluate_with(&self, variables: HashMap<String, f64>) -> Result<f64, EvalError> {
         let mut evaluator = ASTEvaluator::new();
-        for statement in &self.statements {
-            evaluator.visit_statement(statement);
+        for (name, value) in variables {
+            evaluator.define(name, value);
         }
-        evaluator.last_value
+        evaluator.evaluate(self)
+    }
+
+    // Like `evaluate_with`, but reads `variables` in place instead of
+    // copying them into the evaluator; the map is never modified.
+    pub fn evaluate_borrow(&self, variables: &HashMap<String, f64>) -> Result<f64, EvalError> {
+        let mut evaluator = ASTEvaluator::new();
+        evaluator.borrowed = Some(variables);
+        evaluator.evaluate(self)
+    }
+
+    pub fn evaluate_all(&self) -> Result<Vec<f64>, EvalError> {
+        ASTEvaluator::new().evaluate_all(self)
+    }
+
+    pub fn evaluate_with_history(&self) -> Result<Vec<HashMap<String, f64>>, EvalError> {
+        ASTEvaluator::new().evaluate_history(self)
+    }
+
+    pub fn evaluate_with_state(&self) -> (Result<f64, EvalError>, HashMap<String, Value>) {
+        let mut evaluator = ASTEvaluator::new();
+        let result = evaluator.evaluate(self);
+        (result, evaluator.variables().clone())
+    }
+
+    pub fn evaluate_int(&self, overflow: IntOverflow) -> Result<i64, EvalError> {
+        IntEvaluator::new(overflow).evaluate(self)
+    }
+
+    pub fn evaluate_traced(&self) -> (Result<f64, EvalError>, Vec<TraceStep>) {
+        let mut evaluator = ASTEvaluator::new();
+        evaluator.trace = Some(Vec::new());
+        let result = evaluator.evaluate(self);
+        (result, evaluator.trace.unwrap_or_default())
+    }
+
+    // Evaluates the program once per value of `variable`, reusing a single
+    // evaluator and only rebinding that one variable between samples.
+    pub fn evaluate_samples(&self, variable: &str, values: &[f64]) -> Vec<Result<f64, EvalError>> {
+        let mut evaluator = ASTEvaluator::new();
+        evaluator.define(variable.to_string(), 0.0);
+        values
+            .iter()
+            .map(|value| {
+                if let Some(binding) = evaluator.lookup_mut(variable) {
+                    *binding = Value::Number(*value);
+                }
+                evaluator.globals_mut().remove(ANSWER_VARIABLE);
+                evaluator.evaluate(self)
+            })
+            .collect()
     }
 }
 
@@ -52,6 +297,15 @@ pub trait ASTVisitor {
             ASTStatementKind::Expression(expr) => {
                 self.visit_expression(expr);
             }
+            ASTStatementKind::FunctionDeclaration(declaration) => {
+                self.visit_function_declaration(declaration);
+            }
+            ASTStatementKind::VariableDeclaration(declaration) => {
+                self.visit_variable_declaration(declaration);
+            }
+            ASTStatementKind::Print(arguments) => {
+                self.visit_print(arguments);
+            }
         }
     }
     fn visit_statement(&mut self, statement: &ASTStatement) {
@@ -62,6 +316,9 @@ pub trait ASTVisitor {
             ASTExpressionKind::Number(number) => {
                 self.visit_number(number);
             }
+            ASTExpressionKind::Boolean(value) => {
+                self.visit_boolean(*value);
+            }
             ASTExpressionKind::Binary(expr) => {
                 self.visit_binary_expression(expr);
             }
@@ -71,9 +328,23 @@ pub trait ASTVisitor {
             ASTExpressionKind::StartEnd(expr) => {
                 self.visit_start_end_expression(expr);
             }
-            ASTExpressionKind::Variable(variable_name) => {
-                let variable_expression = ASTVariableExpression::new(variable_name.clone());
-                self.visit_variable(&variable_expression);
+            ASTExpressionKind::Absolute(expr) => {
+                self.visit_absolute_expression(expr);
+            }
+            ASTExpressionKind::Unary(expr) => {
+                self.visit_unary_expression(expr);
+            }
+            ASTExpressionKind::Factorial(expr) => {
+                self.visit_factorial_expression(expr);
+            }
+            ASTExpressionKind::Conditional(expr) => {
+                self.visit_conditional_expression(expr);
+            }
+            ASTExpressionKind::Call(expr) => {
+                self.visit_call(expr);
+            }
+            ASTExpressionKind::Variable(variable) => {
+                self.visit_variable(variable);
             }
         }
     }
@@ -81,8 +352,24 @@ pub trait ASTVisitor {
         self.do_visit_expression(expression);
     }
 
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.visit_expression(&declaration.body);
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.visit_expression(&declaration.initializer);
+    }
+
+    fn visit_print(&mut self, arguments: &[ASTExpression]) {
+        for argument in arguments {
+            self.visit_expression(argument);
+        }
+    }
+
     fn visit_number(&mut self, number: &ASTNumberExpression);
 
+    fn visit_boolean(&mut self, _value: bool) {}
+
     fn visit_variable(&mut self, variable: &ASTVariableExpression);
 
     fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
@@ -98,12 +385,254 @@ pub trait ASTVisitor {
     }
 
     fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
-        self.visit_expression(&start_end_expression.expression);
+        for statement in &start_end_expression.statements {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.visit_expression(&absolute_expression.expression);
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.visit_expression(&unary_expression.operand);
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.visit_expression(&conditional.condition);
+        self.visit_expression(&conditional.then_branch);
+        self.visit_expression(&conditional.else_branch);
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        for argument in &call.arguments {
+            self.visit_expression(argument);
+        }
+    }
+}
+
+// Mirrors ASTVisitor for transformations that rewrite the tree in place.
+pub trait ASTMutVisitor {
+    fn do_visit_statement_mut(&mut self, statement: &mut ASTStatement) {
+        match &mut statement.kind {
+            ASTStatementKind::Expression(expr) => {
+                self.visit_expression_mut(expr);
+            }
+            ASTStatementKind::FunctionDeclaration(declaration) => {
+                self.visit_function_declaration_mut(declaration);
+            }
+            ASTStatementKind::VariableDeclaration(declaration) => {
+                self.visit_variable_declaration_mut(declaration);
+            }
+            ASTStatementKind::Print(arguments) => {
+                self.visit_print_mut(arguments);
+            }
+        }
+    }
+    fn visit_statement_mut(&mut self, statement: &mut ASTStatement) {
+        self.do_visit_statement_mut(statement);
+    }
+    fn do_visit_expression_mut(&mut self, expression: &mut ASTExpression) {
+        match &mut expression.kind {
+            ASTExpressionKind::Number(number) => {
+                self.visit_number_mut(number);
+            }
+            ASTExpressionKind::Boolean(value) => {
+                self.visit_boolean_mut(value);
+            }
+            ASTExpressionKind::Binary(expr) => {
+                self.visit_binary_expression_mut(expr);
+            }
+            ASTExpressionKind::Parenthesized(expr) => {
+                self.visit_parenthesized_expression_mut(expr);
+            }
+            ASTExpressionKind::StartEnd(expr) => {
+                self.visit_start_end_expression_mut(expr);
+            }
+            ASTExpressionKind::Absolute(expr) => {
+                self.visit_absolute_expression_mut(expr);
+            }
+            ASTExpressionKind::Unary(expr) => {
+                self.visit_unary_expression_mut(expr);
+            }
+            ASTExpressionKind::Factorial(expr) => {
+                self.visit_factorial_expression_mut(expr);
+            }
+            ASTExpressionKind::Conditional(expr) => {
+                self.visit_conditional_expression_mut(expr);
+            }
+            ASTExpressionKind::Call(expr) => {
+                self.visit_call_mut(expr);
+            }
+            ASTExpressionKind::Variable(variable) => {
+                self.visit_variable_mut(variable);
+            }
+        }
+    }
+    fn visit_expression_mut(&mut self, expression: &mut ASTExpression) {
+        self.do_visit_expression_mut(expression);
+    }
+
+    fn visit_function_declaration_mut(&mut self, declaration: &mut ASTFunctionDeclaration) {
+        self.visit_expression_mut(&mut declaration.body);
+    }
+
+    fn visit_variable_declaration_mut(&mut self, declaration: &mut ASTVariableDeclaration) {
+        self.visit_expression_mut(&mut declaration.initializer);
+    }
+
+    fn visit_print_mut(&mut self, arguments: &mut [ASTExpression]) {
+        for argument in arguments {
+            self.visit_expression_mut(argument);
+        }
+    }
+
+    fn visit_number_mut(&mut self, _number: &mut ASTNumberExpression) {}
+
+    fn visit_boolean_mut(&mut self, _value: &mut bool) {}
+
+    fn visit_variable_mut(&mut self, _variable: &mut ASTVariableExpression) {}
+
+    fn visit_binary_expression_mut(&mut self, binary_expression: &mut ASTBinaryExpression) {
+        self.visit_expression_mut(&mut binary_expression.left);
+        self.visit_expression_mut(&mut binary_expression.right);
+    }
+
+    fn visit_parenthesized_expression_mut(
+        &mut self,
+        parenthesized_expression: &mut ASTParenthesizedExpression,
+    ) {
+        self.visit_expression_mut(&mut parenthesized_expression.expression);
+    }
+
+    fn visit_start_end_expression_mut(&mut self, start_end_expression: &mut ASTStartEndExpression) {
+        for statement in &mut start_end_expression.statements {
+            self.visit_statement_mut(statement);
+        }
+    }
+
+    fn visit_absolute_expression_mut(&mut self, absolute_expression: &mut ASTAbsoluteExpression) {
+        self.visit_expression_mut(&mut absolute_expression.expression);
+    }
+
+    fn visit_unary_expression_mut(&mut self, unary_expression: &mut ASTUnaryExpression) {
+        self.visit_expression_mut(&mut unary_expression.operand);
+    }
+
+    fn visit_factorial_expression_mut(
+        &mut self,
+        factorial_expression: &mut ASTFactorialExpression,
+    ) {
+        self.visit_expression_mut(&mut factorial_expression.expression);
+    }
+
+    fn visit_conditional_expression_mut(&mut self, conditional: &mut ASTConditionalExpression) {
+        self.visit_expression_mut(&mut conditional.condition);
+        self.visit_expression_mut(&mut conditional.then_branch);
+        self.visit_expression_mut(&mut conditional.else_branch);
+    }
+
+    fn visit_call_mut(&mut self, call: &mut ASTCallExpression) {
+        for argument in &mut call.arguments {
+            self.visit_expression_mut(argument);
+        }
+    }
+}
+
+struct VariableRenamer<'a> {
+    from: &'a str,
+    to: &'a str,
+}
+
+impl ASTMutVisitor for VariableRenamer<'_> {
+    fn visit_function_declaration_mut(&mut self, declaration: &mut ASTFunctionDeclaration) {
+        if !declaration
+            .parameters
+            .iter()
+            .any(|parameter| parameter.name == self.from)
+        {
+            self.visit_expression_mut(&mut declaration.body);
+        }
+    }
+
+    fn visit_variable_declaration_mut(&mut self, declaration: &mut ASTVariableDeclaration) {
+        if declaration.name == self.from {
+            declaration.name = self.to.to_string();
+        }
+        self.visit_expression_mut(&mut declaration.initializer);
+    }
+
+    fn visit_variable_mut(&mut self, variable: &mut ASTVariableExpression) {
+        if variable.name == self.from {
+            variable.name = self.to.to_string();
+        }
+    }
+}
+
+// Orders the operands of `+` and `*` so that trees differing only in the
+// order of those operands come out the same: numbers first, smallest first,
+// then variables alphabetically, then anything else as it was.
+struct Canonicalizer;
+
+impl Canonicalizer {
+    fn precedes(left: &ASTExpression, right: &ASTExpression) -> bool {
+        match (&left.kind, &right.kind) {
+            (ASTExpressionKind::Number(left), ASTExpressionKind::Number(right)) => {
+                left.number.total_cmp(&right.number).is_lt()
+            }
+            (ASTExpressionKind::Variable(left), ASTExpressionKind::Variable(right)) => {
+                left.name < right.name
+            }
+            (ASTExpressionKind::Number(_), _) => true,
+            (ASTExpressionKind::Variable(_), ASTExpressionKind::Number(_)) => false,
+            (ASTExpressionKind::Variable(_), _) => true,
+            _ => false,
+        }
+    }
+
+    fn is_percent(expression: &ASTExpression) -> bool {
+        matches!(&expression.kind, ASTExpressionKind::Number(number) if number.percent)
+    }
+}
+
+impl ASTMutVisitor for Canonicalizer {
+    fn visit_binary_expression_mut(&mut self, binary_expression: &mut ASTBinaryExpression) {
+        self.visit_expression_mut(&mut binary_expression.left);
+        self.visit_expression_mut(&mut binary_expression.right);
+        // `200 + 5%` is relative to its left operand, so a `+` next to a
+        // percent literal is not commutative.
+        let commutative = match binary_expression.operator.kind {
+            ASTBinaryOperatorKind::Plus => {
+                !Self::is_percent(&binary_expression.left)
+                    && !Self::is_percent(&binary_expression.right)
+            }
+            ASTBinaryOperatorKind::Multiply => true,
+            _ => false,
+        };
+        if commutative && Self::precedes(&binary_expression.right, &binary_expression.left) {
+            core::mem::swap(&mut binary_expression.left, &mut binary_expression.right);
+        }
+    }
+}
+
+// Adapts an `io::Write` sink such as stdout or a file so printers can target it.
+#[cfg(feature = "std")]
+pub struct IoWriter<W: io::Write>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: io::Write> fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_all(s.as_bytes()).map_err(|_| fmt::Error)
     }
 }
 
 pub struct ASTPrinter {
     indent: usize,
+    output: String,
 }
 const LEVEL_INDENT: usize = 2;
 
@@ -122,13 +651,49 @@ impl ASTVisitor for ASTPrinter {
         self.indent -= LEVEL_INDENT;
     }
 
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.print_with_indent(&format!(
+            "Def {}({})=",
+            declaration.name,
+            declaration
+                .parameters
+                .iter()
+                .map(|parameter| parameter.name.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        ));
+        self.visit_expression(&declaration.body);
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.print_with_indent(&format!("Var {}=", declaration.name));
+        self.visit_expression(&declaration.initializer);
+    }
+
+    fn visit_print(&mut self, arguments: &[ASTExpression]) {
+        self.print_with_indent("Print");
+        for argument in arguments {
+            self.visit_expression(argument);
+        }
+    }
+
     fn visit_number(&mut self, number: &ASTNumberExpression) {
         let number_str = if number.number.fract() == 0.0 {
             format!("{}", number.number) + ".0"
         } else {
             format!("{}", number.number)
         };
-        self.print_with_indent(&format!("Real({})", number_str));
+        let percent = if number.percent { "%" } else { "" };
+        match &number.unit {
+            Some(unit) => {
+                self.print_with_indent(&format!("Real({} {}{})", number_str, unit, percent))
+            }
+            None => self.print_with_indent(&format!("Real({}{})", number_str, percent)),
+        }
+    }
+
+    fn visit_boolean(&mut self, value: bool) {
+        self.print_with_indent(&format!("Bool({})", value));
     }
 
     fn visit_variable(&mut self, variable: &ASTVariableExpression) {
@@ -159,22 +724,88 @@ impl ASTVisitor for ASTPrinter {
 
     fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
         self.print_with_indent("{");
-        // self.indent += LEVEL_INDENT;
-        self.visit_expression(&start_end_expression.expression);
+        for statement in &start_end_expression.statements {
+            ASTVisitor::do_visit_statement(self, statement);
+        }
 
         self.print_with_indent("}");
-        //self.indent -= LEVEL_INDENT;
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.print_with_indent("|");
+        self.visit_expression(&absolute_expression.expression);
+        self.print_with_indent("|");
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.print_with_indent(&format!("{:?}(", unary_expression.operator));
+        self.visit_expression(&unary_expression.operand);
+        self.print_with_indent(")");
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+        self.print_with_indent("!");
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.print_with_indent("If(");
+        self.visit_expression(&conditional.condition);
+        self.print_with_indent(",");
+        self.visit_expression(&conditional.then_branch);
+        self.print_with_indent(",");
+        self.visit_expression(&conditional.else_branch);
+        self.print_with_indent(")");
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        self.print_with_indent(&format!("{}(", call.name));
+        for (index, argument) in call.arguments.iter().enumerate() {
+            if index > 0 {
+                self.print_with_indent(",");
+            }
+            self.visit_expression(argument);
+        }
+        self.print_with_indent(")");
+    }
+}
+
+impl Default for ASTPrinter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl ASTPrinter {
+    pub fn new() -> Self {
+        Self {
+            indent: 1,
+            output: String::new(),
+        }
+    }
+
+    pub fn print_to<W: fmt::Write>(&self, ast: &Ast, out: &mut W) -> fmt::Result {
+        out.write_str(&self.render(ast))
+    }
+
+    fn render(&self, ast: &Ast) -> String {
+        let mut printer = ASTPrinter {
+            indent: self.indent,
+            output: String::new(),
+        };
+        ast.visit(&mut printer);
+        printer.output
+    }
+
     fn print_with_indent(&mut self, text: &str) {
-        print!("{}{}", "".repeat(self.indent), text);
+        self.output.push_str(&"".repeat(self.indent));
+        self.output.push_str(text);
     }
 }
 
 pub struct ASTXMLPrinter {
     indent: usize,
+    output: String,
 }
 
 impl ASTVisitor for ASTXMLPrinter {
@@ -192,28 +823,78 @@ impl ASTVisitor for ASTXMLPrinter {
         self.indent -= LEVEL_INDENT;
     }
 
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.print_with_indent(&format!(
+            "<def name=\"{}\">\n",
+            Self::escape(&declaration.name)
+        ));
+        for parameter in &declaration.parameters {
+            self.print_with_indent(&format!(
+                "<param>{}</param>\n",
+                Self::escape(&parameter.name)
+            ));
+        }
+        self.visit_expression(&declaration.body);
+        self.print_with_indent("</def>\n");
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.print_with_indent(&format!(
+            "<var name=\"{}\">\n",
+            Self::escape(&declaration.name)
+        ));
+        self.visit_expression(&declaration.initializer);
+        self.print_with_indent("</var>\n");
+    }
+
+    fn visit_print(&mut self, arguments: &[ASTExpression]) {
+        self.print_with_indent("<print>\n");
+        for argument in arguments {
+            self.visit_expression(argument);
+        }
+        self.print_with_indent("</print>\n");
+    }
+
     fn visit_number(&mut self, number: &ASTNumberExpression) {
         let number_str = if number.number.fract() == 0.0 {
             format!("{}", number.number) + ".0"
         } else {
             format!("{}", number.number)
         };
-        self.print_with_indent(&format!("<real>{}</real>\n", number_str));
+        let percent = if number.percent {
+            " percent=\"true\""
+        } else {
+            ""
+        };
+        match &number.unit {
+            Some(unit) => self.print_with_indent(&format!(
+                "<real unit=\"{}\"{}>{}</real>\n",
+                unit, percent, number_str
+            )),
+            None => self.print_with_indent(&format!("<real{}>{}</real>\n", percent, number_str)),
+        }
+    }
+
+    fn visit_boolean(&mut self, value: bool) {
+        self.print_with_indent(&format!("<bool>{}</bool>\n", value));
     }
 
     fn visit_variable(&mut self, variable: &ASTVariableExpression) {
-        self.print_with_indent(&format!("<variable>{}</variable>\n", variable.name));
+        self.print_with_indent(&format!(
+            "<variable>{}</variable>\n",
+            Self::escape(&variable.name)
+        ));
     }
 
     fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
-        self.print_with_indent(&format!("<{:?}>", binary_expression.operator.kind));
+        self.print_with_indent(&format!("<{}>", binary_expression.operator.kind.tag()));
 
         self.print_with_indent("\n"); // Space added
         self.visit_expression(&binary_expression.left);
 
         self.print_with_indent("\n"); // Space added
         self.visit_expression(&binary_expression.right);
-        self.print_with_indent(&format!("</{:?}>\n", binary_expression.operator.kind));
+        self.print_with_indent(&format!("</{}>\n", binary_expression.operator.kind.tag()));
     }
     fn visit_parenthesized_expression(
         &mut self,
@@ -229,51 +910,456 @@ impl ASTVisitor for ASTXMLPrinter {
 
     fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
         self.print_with_indent("{");
-        // self.indent += LEVEL_INDENT;
-        self.visit_expression(&start_end_expression.expression);
+        for statement in &start_end_expression.statements {
+            ASTVisitor::do_visit_statement(self, statement);
+        }
 
         self.print_with_indent("}");
-        //self.indent -= LEVEL_INDENT;
     }
-}
 
-impl ASTXMLPrinter {
-    fn print_with_indent(&mut self, text: &str) {
-        print!("{}{}", "".repeat(self.indent), text);
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.print_with_indent("<abs>\n");
+        self.visit_expression(&absolute_expression.expression);
+        self.print_with_indent("</abs>\n");
     }
-}
 
-#[derive(Debug)]
-pub enum ASTStatementKind {
-    Expression(ASTExpression),
-}
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.print_with_indent(&format!("<{}>\n", unary_expression.operator.tag()));
+        self.visit_expression(&unary_expression.operand);
+        self.print_with_indent(&format!("</{}>\n", unary_expression.operator.tag()));
+    }
 
-#[derive(Debug)]
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.print_with_indent("<fact>\n");
+        self.visit_expression(&factorial_expression.expression);
+        self.print_with_indent("</fact>\n");
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.print_with_indent("<if>\n");
+        self.visit_expression(&conditional.condition);
+        self.print_with_indent("<then>\n");
+        self.visit_expression(&conditional.then_branch);
+        self.print_with_indent("</then>\n<else>\n");
+        self.visit_expression(&conditional.else_branch);
+        self.print_with_indent("</else>\n</if>\n");
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        self.print_with_indent(&format!("<call name=\"{}\">\n", Self::escape(&call.name)));
+        for argument in &call.arguments {
+            self.visit_expression(argument);
+        }
+        self.print_with_indent("</call>\n");
+    }
+}
+
+impl Default for ASTXMLPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ASTXMLPrinter {
+    pub fn new() -> Self {
+        Self {
+            indent: 1,
+            output: String::new(),
+        }
+    }
+
+    pub fn print_to<W: fmt::Write>(&self, ast: &Ast, out: &mut W) -> fmt::Result {
+        out.write_str(&self.render(ast))
+    }
+
+    fn render(&self, ast: &Ast) -> String {
+        let mut printer = ASTXMLPrinter {
+            indent: self.indent,
+            output: String::new(),
+        };
+        printer
+            .output
+            .push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<program>\n");
+        ast.visit(&mut printer);
+
+        printer.output.push_str("</program>");
+        printer.output
+    }
+
+    fn print_with_indent(&mut self, text: &str) {
+        self.output.push_str(&"".repeat(self.indent));
+        self.output.push_str(text);
+    }
+
+    // Backtick-quoted names may contain any character, markup included.
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+}
+
+// Renders the tree back as source text that evaluates the same way.
+// Parentheses are written only where precedence or associativity needs
+// them, so `2 - (3 - 4)` keeps its pair and `(2 - 3) - 4` loses it.
+pub struct ASTSourcePrinter {
+    output: String,
+}
+
+impl ASTVisitor for ASTSourcePrinter {
+    fn visit_statement(&mut self, statement: &ASTStatement) {
+        if !self.output.is_empty() {
+            self.output.push_str("; ");
+        }
+        ASTVisitor::do_visit_statement(self, statement);
+    }
+
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        let parameters = declaration
+            .parameters
+            .iter()
+            .map(|parameter| Self::identifier(&parameter.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.output.push_str(&format!(
+            "def {}({}) = ",
+            Self::identifier(&declaration.name),
+            parameters
+        ));
+        self.visit_expression(&declaration.body);
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        let keyword = if declaration.assignment { "" } else { "var " };
+        self.output.push_str(&format!(
+            "{}{} = ",
+            keyword,
+            Self::identifier(&declaration.name)
+        ));
+        self.visit_expression(&declaration.initializer);
+    }
+
+    fn visit_print(&mut self, arguments: &[ASTExpression]) {
+        self.output.push_str("print ");
+        for (index, argument) in arguments.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            self.visit_expression(argument);
+        }
+    }
+
+    fn visit_number(&mut self, number: &ASTNumberExpression) {
+        self.output.push_str(&number.number.to_string());
+        if let Some(unit) = &number.unit {
+            self.output.push_str(unit);
+        }
+        if number.percent {
+            self.output.push('%');
+        }
+    }
+
+    fn visit_boolean(&mut self, value: bool) {
+        self.output.push_str(if value { "true" } else { "false" });
+    }
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        self.output.push_str(&Self::identifier(&variable.name));
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        let operator = &binary_expression.operator;
+        let left = Self::binding(&binary_expression.left);
+        let right = Self::binding(&binary_expression.right);
+        let left_parens = left < operator.precedence()
+            || (left == operator.precedence() && operator.is_right_associative());
+        let right_parens = right < operator.precedence()
+            || (right == operator.precedence() && !operator.is_right_associative());
+        self.operand(&binary_expression.left, left_parens);
+        self.output.push_str(&format!(" {} ", operator.kind));
+        self.operand(&binary_expression.right, right_parens);
+    }
+
+    // The enclosing operator decides whether the pair is needed.
+    fn visit_parenthesized_expression(
+        &mut self,
+        parenthesized_expression: &ASTParenthesizedExpression,
+    ) {
+        self.visit_expression(&parenthesized_expression.expression);
+    }
+
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        self.output.push('{');
+        for (index, statement) in start_end_expression.statements.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str("; ");
+            }
+            ASTVisitor::do_visit_statement(self, statement);
+        }
+        self.output.push('}');
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.output.push('|');
+        self.visit_expression(&absolute_expression.expression);
+        self.output.push('|');
+    }
+
+    // The operand of `-` is parsed at `^` precedence.
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        match unary_expression.operator {
+            ASTUnaryOperatorKind::Neg => self.output.push('-'),
+        }
+        let parens = Self::binding(&unary_expression.operand) < 5;
+        self.operand(&unary_expression.operand, parens);
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        let parens = Self::binding(&factorial_expression.expression) < u8::MAX;
+        self.operand(&factorial_expression.expression, parens);
+        self.output.push('!');
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.output.push_str("if ");
+        self.visit_expression(&conditional.condition);
+        self.output.push_str(" then ");
+        self.visit_expression(&conditional.then_branch);
+        self.output.push_str(" else ");
+        self.visit_expression(&conditional.else_branch);
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        self.output.push_str(&Self::identifier(&call.name));
+        self.output.push('(');
+        for (index, argument) in call.arguments.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            self.visit_expression(argument);
+        }
+        self.output.push(')');
+    }
+}
+
+impl Default for ASTSourcePrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ASTSourcePrinter {
+    pub fn new() -> Self {
+        Self {
+            output: String::new(),
+        }
+    }
+
+    pub fn print_to<W: fmt::Write>(&self, ast: &Ast, out: &mut W) -> fmt::Result {
+        out.write_str(&self.render(ast))
+    }
+
+    fn render(&self, ast: &Ast) -> String {
+        let mut printer = ASTSourcePrinter::new();
+        ast.visit(&mut printer);
+        printer.output
+    }
+
+    fn render_expression(expression: &ASTExpression) -> String {
+        let mut printer = ASTSourcePrinter::new();
+        printer.visit_expression(expression);
+        printer.output
+    }
+
+    // Names that would not lex back as a single identifier, such as
+    // `total amount` or a keyword, are written between backticks.
+    fn identifier(name: &str) -> String {
+        let (tokens, _) = Lexer::tokenize(name);
+        match tokens.as_slice() {
+            [Token {
+                kind: TokenKind::Variable(lexed),
+                ..
+            }] if lexed == name => name.to_string(),
+            _ => format!("`{}`", name),
+        }
+    }
+
+    // How tightly an expression holds together as an operand, on the scale of
+    // `ASTBinaryOperator::precedence`. Atoms never need parentheses; a
+    // conditional always does, since its `else` branch would swallow
+    // whatever follows.
+    fn binding(expression: &ASTExpression) -> u8 {
+        match &expression.kind {
+            ASTExpressionKind::Binary(binary_expression) => binary_expression.operator.precedence(),
+            // Like `^`, so `(-2) ^ 2` keeps its parentheses.
+            ASTExpressionKind::Unary(_) => 5,
+            ASTExpressionKind::Conditional(_) => 0,
+            ASTExpressionKind::Parenthesized(parenthesized) => {
+                Self::binding(&parenthesized.expression)
+            }
+            _ => u8::MAX,
+        }
+    }
+
+    fn operand(&mut self, expression: &ASTExpression, parens: bool) {
+        if parens {
+            self.output.push('(');
+        }
+        self.visit_expression(expression);
+        if parens {
+            self.output.push(')');
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ASTStatementKind {
+    Expression(ASTExpression),
+    FunctionDeclaration(ASTFunctionDeclaration),
+    VariableDeclaration(ASTVariableDeclaration),
+    // `print a, b`: every value is appended to `ASTEvaluator::output`.
+    Print(Vec<ASTExpression>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ASTFunctionDeclaration {
+    name: String,
+    parameters: Vec<ASTVariableExpression>,
+    body: ASTExpression,
+}
+
+#[derive(Debug, Clone)]
+pub struct ASTVariableDeclaration {
+    name: String,
+    // Covers the declared name only.
+    span: TextSpan,
+    initializer: ASTExpression,
+    // Written as a bare `name = value`, which updates the nearest existing
+    // binding and only declares one when there is none.
+    assignment: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct ASTStatement {
     pub kind: ASTStatementKind,
+    // Text of the `##` doc comment written just before the statement.
+    pub doc: Option<String>,
+    // From the start of the first token to the end of the last, without the
+    // `;` that ends the statement.
+    span: TextSpan,
 }
 
 impl ASTStatement {
+    // Statements built outside the parser get an empty span at the start of
+    // the input, like `ASTBinaryOperator::synthetic`.
     pub fn new(kind: ASTStatementKind) -> Self {
-        ASTStatement { kind }
+        ASTStatement {
+            kind,
+            doc: None,
+            span: TextSpan::new(0, 0, String::new()),
+        }
+    }
+
+    // Where the statement was written, for pointing a runtime error at the
+    // statement that raised it.
+    pub fn span(&self) -> TextSpan {
+        self.span.clone()
     }
 
     pub fn expression(expr: ASTExpression) -> Self {
         ASTStatement::new(ASTStatementKind::Expression(expr))
     }
+
+    pub fn function_declaration(
+        name: String,
+        parameters: Vec<ASTVariableExpression>,
+        body: ASTExpression,
+    ) -> Self {
+        ASTStatement::new(ASTStatementKind::FunctionDeclaration(
+            ASTFunctionDeclaration {
+                name,
+                parameters,
+                body,
+            },
+        ))
+    }
+
+    pub fn variable_declaration(name: String, span: TextSpan, initializer: ASTExpression) -> Self {
+        ASTStatement::new(ASTStatementKind::VariableDeclaration(
+            ASTVariableDeclaration {
+                name,
+                span,
+                initializer,
+                assignment: false,
+            },
+        ))
+    }
+
+    pub fn assignment(name: String, span: TextSpan, initializer: ASTExpression) -> Self {
+        ASTStatement::new(ASTStatementKind::VariableDeclaration(
+            ASTVariableDeclaration {
+                name,
+                span,
+                initializer,
+                assignment: true,
+            },
+        ))
+    }
+
+    pub fn print(arguments: Vec<ASTExpression>) -> Self {
+        ASTStatement::new(ASTStatementKind::Print(arguments))
+    }
+
+    // The expressions directly inside this statement.
+    fn expressions(&self) -> Vec<&ASTExpression> {
+        match &self.kind {
+            ASTStatementKind::Expression(expression) => vec![expression],
+            ASTStatementKind::VariableDeclaration(declaration) => vec![&declaration.initializer],
+            ASTStatementKind::FunctionDeclaration(declaration) => vec![&declaration.body],
+            ASTStatementKind::Print(arguments) => arguments.iter().collect(),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ASTExpressionKind {
     Number(ASTNumberExpression),
     Binary(ASTBinaryExpression),
     Parenthesized(ASTParenthesizedExpression),
     StartEnd(ASTStartEndExpression),
+    Absolute(ASTAbsoluteExpression),
+    Unary(ASTUnaryExpression),
+    Factorial(ASTFactorialExpression),
+    Conditional(ASTConditionalExpression),
+    Call(ASTCallExpression),
+    Boolean(bool),
+
+    Variable(ASTVariableExpression),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ASTUnaryOperatorKind {
+    Neg,
+}
 
-    Variable(String),
+impl ASTUnaryOperatorKind {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ASTUnaryOperatorKind::Neg => "neg",
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ASTBinaryOperatorKind {
     Plus,
     Minus,
@@ -281,10 +1367,62 @@ pub enum ASTBinaryOperatorKind {
     Divide,
     Pow,
     IntegerDivide,
-    Eof,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    BitXor,
+    // `@`, whose meaning is supplied at runtime through
+    // `ASTEvaluator::register_operator`.
+    At,
+}
+
+impl ASTBinaryOperatorKind {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            ASTBinaryOperatorKind::Plus => "add",
+            ASTBinaryOperatorKind::Minus => "sub",
+            ASTBinaryOperatorKind::Multiply => "mul",
+            ASTBinaryOperatorKind::Divide => "div",
+            ASTBinaryOperatorKind::Pow => "pow",
+            ASTBinaryOperatorKind::IntegerDivide => "idiv",
+            ASTBinaryOperatorKind::Less => "lt",
+            ASTBinaryOperatorKind::LessEqual => "le",
+            ASTBinaryOperatorKind::Greater => "gt",
+            ASTBinaryOperatorKind::GreaterEqual => "ge",
+            ASTBinaryOperatorKind::Equal => "eq",
+            ASTBinaryOperatorKind::NotEqual => "ne",
+            ASTBinaryOperatorKind::BitXor => "xor",
+            ASTBinaryOperatorKind::At => "at",
+        }
+    }
+}
+
+impl fmt::Display for ASTBinaryOperatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            ASTBinaryOperatorKind::Plus => "+",
+            ASTBinaryOperatorKind::Minus => "-",
+            ASTBinaryOperatorKind::Multiply => "*",
+            ASTBinaryOperatorKind::Divide => "/",
+            ASTBinaryOperatorKind::Pow => "^",
+            ASTBinaryOperatorKind::IntegerDivide => "//",
+            ASTBinaryOperatorKind::Less => "<",
+            ASTBinaryOperatorKind::LessEqual => "<=",
+            ASTBinaryOperatorKind::Greater => ">",
+            ASTBinaryOperatorKind::GreaterEqual => ">=",
+            ASTBinaryOperatorKind::Equal => "==",
+            ASTBinaryOperatorKind::NotEqual => "!=",
+            ASTBinaryOperatorKind::BitXor => "^",
+            ASTBinaryOperatorKind::At => "@",
+        };
+        f.write_str(symbol)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ASTBinaryOperator {
     kind: ASTBinaryOperatorKind,
     token: Token,
@@ -295,65 +1433,332 @@ impl ASTBinaryOperator {
         ASTBinaryOperator { kind, token }
     }
 
+    pub fn kind(&self) -> &ASTBinaryOperatorKind {
+        &self.kind
+    }
+
+    // Where the operator itself was written, e.g. for a hover tooltip.
+    pub fn span(&self) -> &TextSpan {
+        &self.token.span
+    }
+
+    // Operators built outside the parser get a token with an empty span at the
+    // start of the input, since there is no source text to point at.
+    pub fn synthetic(kind: ASTBinaryOperatorKind) -> Self {
+        let token_kind = match kind {
+            ASTBinaryOperatorKind::Plus => TokenKind::Plus,
+            ASTBinaryOperatorKind::Minus => TokenKind::Minus,
+            ASTBinaryOperatorKind::Multiply => TokenKind::Asterisk,
+            ASTBinaryOperatorKind::Divide => TokenKind::Slash,
+            ASTBinaryOperatorKind::Pow => TokenKind::Pow,
+            ASTBinaryOperatorKind::IntegerDivide => TokenKind::IntegerDivide,
+            ASTBinaryOperatorKind::Less => TokenKind::Less,
+            ASTBinaryOperatorKind::LessEqual => TokenKind::LessEqual,
+            ASTBinaryOperatorKind::Greater => TokenKind::Greater,
+            ASTBinaryOperatorKind::GreaterEqual => TokenKind::GreaterEqual,
+            ASTBinaryOperatorKind::Equal => TokenKind::EqualEqual,
+            ASTBinaryOperatorKind::NotEqual => TokenKind::BangEqual,
+            ASTBinaryOperatorKind::BitXor => TokenKind::Xor,
+            ASTBinaryOperatorKind::At => TokenKind::At,
+        };
+        let token = Token::new(token_kind, TextSpan::new(0, 0, kind.to_string()));
+        ASTBinaryOperator::new(kind, token)
+    }
+
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self.kind, ASTBinaryOperatorKind::Pow)
+    }
+
     pub fn precedence(&self) -> u8 {
         match self.kind {
-            ASTBinaryOperatorKind::Eof => 1,
-            ASTBinaryOperatorKind::Plus => 1,
-            ASTBinaryOperatorKind::Minus => 1,
-            ASTBinaryOperatorKind::Multiply => 2,
-            ASTBinaryOperatorKind::Divide => 2,
-            ASTBinaryOperatorKind::IntegerDivide => 2,
-            ASTBinaryOperatorKind::Pow => 3,
+            ASTBinaryOperatorKind::Less => 1,
+            ASTBinaryOperatorKind::LessEqual => 1,
+            ASTBinaryOperatorKind::Greater => 1,
+            ASTBinaryOperatorKind::GreaterEqual => 1,
+            ASTBinaryOperatorKind::Equal => 1,
+            ASTBinaryOperatorKind::NotEqual => 1,
+            // Unlike C, XOR binds tighter than comparisons so that
+            // `5 ^ 3 == 6` compares the result.
+            ASTBinaryOperatorKind::BitXor => 2,
+            ASTBinaryOperatorKind::Plus => 3,
+            ASTBinaryOperatorKind::Minus => 3,
+            ASTBinaryOperatorKind::Multiply => 4,
+            ASTBinaryOperatorKind::Divide => 4,
+            ASTBinaryOperatorKind::IntegerDivide => 4,
+            ASTBinaryOperatorKind::Pow => 5,
+            // The parser can be configured to use another precedence.
+            ASTBinaryOperatorKind::At => 4,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ASTBinaryExpression {
     left: Box<ASTExpression>,
     operator: ASTBinaryOperator,
     right: Box<ASTExpression>,
 }
 
-#[derive(Debug)]
+impl ASTBinaryExpression {
+    pub fn left(&self) -> &ASTExpression {
+        &self.left
+    }
+
+    pub fn operator(&self) -> &ASTBinaryOperator {
+        &self.operator
+    }
+
+    pub fn right(&self) -> &ASTExpression {
+        &self.right
+    }
+
+    // `200 + 5%`: the percentage is taken of the left operand.
+    fn is_percent_change(&self) -> bool {
+        matches!(
+            self.operator.kind,
+            ASTBinaryOperatorKind::Plus | ASTBinaryOperatorKind::Minus
+        ) && matches!(&self.right.kind, ASTExpressionKind::Number(number) if number.percent)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ASTNumberExpression {
     number: f64,
+    unit: Option<String>,
+    // Written with a `%` suffix. On its own `5%` is `0.05`, but as the right
+    // operand of `+` or `-` it is relative to the left operand, so
+    // `200 + 5%` is `200 * 1.05`. `%` is never a remainder operator.
+    percent: bool,
+}
+impl ASTNumberExpression {
+    pub fn number(&self) -> f64 {
+        self.number
+    }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
+    pub fn is_percent(&self) -> bool {
+        self.percent
+    }
+
+    pub fn number_mut(&mut self) -> &mut f64 {
+        &mut self.number
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ASTVariableExpression {
     name: String,
+    span: TextSpan,
 }
 impl ASTVariableExpression {
-    pub fn new(name: String) -> Self {
-        ASTVariableExpression { name }
+    pub fn new(name: String, span: TextSpan) -> Self {
+        ASTVariableExpression { name, span }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn span(&self) -> &TextSpan {
+        &self.span
     }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ASTParenthesizedExpression {
     expression: Box<ASTExpression>,
 }
-#[derive(Debug)]
+// `{ a = 1; a + 2 }`: the statements run in a scope of their own and the
+// block's value is that of the last one.
+#[derive(Debug, Clone)]
 pub struct ASTStartEndExpression {
+    statements: Vec<ASTStatement>,
+}
+#[derive(Debug, Clone)]
+pub struct ASTAbsoluteExpression {
+    expression: Box<ASTExpression>,
+}
+#[derive(Debug, Clone)]
+pub struct ASTUnaryExpression {
+    operator: ASTUnaryOperatorKind,
+    operand: Box<ASTExpression>,
+}
+#[derive(Debug, Clone)]
+pub struct ASTFactorialExpression {
     expression: Box<ASTExpression>,
 }
+#[derive(Debug, Clone)]
+pub struct ASTConditionalExpression {
+    condition: Box<ASTExpression>,
+    then_branch: Box<ASTExpression>,
+    else_branch: Box<ASTExpression>,
+}
+#[derive(Debug, Clone)]
+pub struct ASTCallExpression {
+    name: String,
+    arguments: Vec<ASTExpression>,
+}
+impl ASTCallExpression {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arguments(&self) -> &[ASTExpression] {
+        &self.arguments
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ASTExpression {
     kind: ASTExpressionKind,
+    // Byte offsets `start..end` of the source text the parser built this
+    // from; `None` for expressions built in code.
+    span: Option<(usize, usize)>,
+}
+
+// Long operator chains nest as deep as they are long, and the derived drop
+// glue would recurse once per level. Children are detached onto a work list
+// instead, so each one is dropped with no children left.
+impl Drop for ASTExpression {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.detach_children(&mut pending);
+        while let Some(mut expression) = pending.pop() {
+            expression.detach_children(&mut pending);
+        }
+    }
 }
 
 impl ASTExpression {
     pub fn new(kind: ASTExpressionKind) -> Self {
-        ASTExpression { kind }
+        ASTExpression { kind, span: None }
+    }
+
+    pub fn with_span(mut self, start: usize, end: usize) -> Self {
+        self.span = Some((start, end));
+        self
+    }
+
+    pub fn kind(&self) -> &ASTExpressionKind {
+        &self.kind
+    }
+
+    pub fn span(&self) -> Option<(usize, usize)> {
+        self.span
+    }
+
+    fn contains(&self, offset: usize) -> bool {
+        self.span
+            .is_some_and(|(start, end)| start <= offset && offset < end)
+    }
+
+    // Direct subexpressions, for walks that keep their own stack.
+    fn children(&self) -> Vec<&ASTExpression> {
+        match &self.kind {
+            ASTExpressionKind::Number(_)
+            | ASTExpressionKind::Boolean(_)
+            | ASTExpressionKind::Variable(_) => vec![],
+            ASTExpressionKind::Binary(binary) => vec![&binary.left, &binary.right],
+            ASTExpressionKind::Parenthesized(ASTParenthesizedExpression { expression })
+            | ASTExpressionKind::Absolute(ASTAbsoluteExpression { expression })
+            | ASTExpressionKind::Factorial(ASTFactorialExpression { expression })
+            | ASTExpressionKind::Unary(ASTUnaryExpression {
+                operand: expression,
+                ..
+            }) => vec![expression],
+            ASTExpressionKind::StartEnd(block) => block
+                .statements
+                .iter()
+                .flat_map(ASTStatement::expressions)
+                .collect(),
+            ASTExpressionKind::Conditional(conditional) => vec![
+                &conditional.condition,
+                &conditional.then_branch,
+                &conditional.else_branch,
+            ],
+            ASTExpressionKind::Call(call) => call.arguments.iter().collect(),
+        }
+    }
+
+    fn detach_children(&mut self, pending: &mut Vec<ASTExpression>) {
+        let leaf = ASTExpressionKind::Number(ASTNumberExpression {
+            number: 0.0,
+            unit: None,
+            percent: false,
+        });
+        match core::mem::replace(&mut self.kind, leaf) {
+            ASTExpressionKind::Number(_)
+            | ASTExpressionKind::Boolean(_)
+            | ASTExpressionKind::Variable(_) => {}
+            ASTExpressionKind::Binary(binary) => {
+                pending.push(*binary.left);
+                pending.push(*binary.right);
+            }
+            ASTExpressionKind::Parenthesized(ASTParenthesizedExpression { expression })
+            | ASTExpressionKind::Absolute(ASTAbsoluteExpression { expression })
+            | ASTExpressionKind::Factorial(ASTFactorialExpression { expression })
+            | ASTExpressionKind::Unary(ASTUnaryExpression {
+                operand: expression,
+                ..
+            }) => pending.push(*expression),
+            ASTExpressionKind::StartEnd(block) => {
+                for statement in block.statements {
+                    match statement.kind {
+                        ASTStatementKind::Expression(expression) => pending.push(expression),
+                        ASTStatementKind::VariableDeclaration(declaration) => {
+                            pending.push(declaration.initializer)
+                        }
+                        ASTStatementKind::FunctionDeclaration(declaration) => {
+                            pending.push(declaration.body)
+                        }
+                        ASTStatementKind::Print(arguments) => pending.extend(arguments),
+                    }
+                }
+            }
+            ASTExpressionKind::Conditional(conditional) => {
+                pending.push(*conditional.condition);
+                pending.push(*conditional.then_branch);
+                pending.push(*conditional.else_branch);
+            }
+            ASTExpressionKind::Call(call) => pending.extend(call.arguments),
+        }
     }
 
     pub fn number(number: f64) -> Self {
-        ASTExpression::new(ASTExpressionKind::Number(ASTNumberExpression { number }))
+        ASTExpression::number_with_unit(number, None)
+    }
+
+    pub fn number_with_unit(number: f64, unit: Option<String>) -> Self {
+        ASTExpression::new(ASTExpressionKind::Number(ASTNumberExpression {
+            number,
+            unit,
+            percent: false,
+        }))
+    }
+
+    pub fn boolean(value: bool) -> Self {
+        ASTExpression::new(ASTExpressionKind::Boolean(value))
     }
 
-    pub fn variable(name: String) -> Self {
-        ASTExpression::new(ASTExpressionKind::Variable(name))
+    pub fn percent(number: f64) -> Self {
+        ASTExpression::number(number).into_percent()
+    }
+
+    // Marks a number literal as written with `%`; other expressions are
+    // returned unchanged.
+    pub fn into_percent(mut self) -> Self {
+        if let ASTExpressionKind::Number(number) = &mut self.kind {
+            number.percent = true;
+        }
+        self
+    }
+
+    pub fn variable(name: String, span: TextSpan) -> Self {
+        ASTExpression::new(ASTExpressionKind::Variable(ASTVariableExpression::new(
+            name, span,
+        )))
     }
 
     pub fn binary(operator: ASTBinaryOperator, left: ASTExpression, right: ASTExpression) -> Self {
@@ -372,83 +1777,1178 @@ impl ASTExpression {
         ))
     }
     pub fn start_end(expression: ASTExpression) -> Self {
+        ASTExpression::block(vec![ASTStatement::expression(expression)])
+    }
+
+    pub fn block(statements: Vec<ASTStatement>) -> Self {
         ASTExpression::new(ASTExpressionKind::StartEnd(ASTStartEndExpression {
+            statements,
+        }))
+    }
+
+    pub fn absolute(expression: ASTExpression) -> Self {
+        ASTExpression::new(ASTExpressionKind::Absolute(ASTAbsoluteExpression {
+            expression: Box::new(expression),
+        }))
+    }
+
+    pub fn unary(operator: ASTUnaryOperatorKind, operand: ASTExpression) -> Self {
+        ASTExpression::new(ASTExpressionKind::Unary(ASTUnaryExpression {
+            operator,
+            operand: Box::new(operand),
+        }))
+    }
+
+    pub fn factorial(expression: ASTExpression) -> Self {
+        ASTExpression::new(ASTExpressionKind::Factorial(ASTFactorialExpression {
             expression: Box::new(expression),
         }))
     }
-}
 
-use std::collections::HashMap;
+    pub fn conditional(
+        condition: ASTExpression,
+        then_branch: ASTExpression,
+        else_branch: ASTExpression,
+    ) -> Self {
+        ASTExpression::new(ASTExpressionKind::Conditional(ASTConditionalExpression {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        }))
+    }
 
-pub struct ASTEvaluator {
-    pub last_value: Option<f64>,
-    pub variables: HashMap<String, f64>,
+    pub fn call(name: String, arguments: Vec<ASTExpression>) -> Self {
+        ASTExpression::new(ASTExpressionKind::Call(ASTCallExpression {
+            name,
+            arguments,
+        }))
+    }
 }
 
-impl ASTEvaluator {
-    pub fn new() -> Self {
-        Self {
-            last_value: None,
-            variables: HashMap::new(),
+impl ASTStatement {
+    fn approx_eq(&self, other: &ASTStatement, epsilon: f64) -> bool {
+        match (&self.kind, &other.kind) {
+            (ASTStatementKind::Expression(left), ASTStatementKind::Expression(right)) => {
+                left.approx_eq(right, epsilon)
+            }
+            (
+                ASTStatementKind::FunctionDeclaration(left),
+                ASTStatementKind::FunctionDeclaration(right),
+            ) => {
+                left.name == right.name
+                    && left
+                        .parameters
+                        .iter()
+                        .map(|parameter| &parameter.name)
+                        .eq(right.parameters.iter().map(|parameter| &parameter.name))
+                    && left.body.approx_eq(&right.body, epsilon)
+            }
+            (
+                ASTStatementKind::VariableDeclaration(left),
+                ASTStatementKind::VariableDeclaration(right),
+            ) => {
+                left.name == right.name
+                    && left.assignment == right.assignment
+                    && left.initializer.approx_eq(&right.initializer, epsilon)
+            }
+            (ASTStatementKind::Print(left), ASTStatementKind::Print(right)) => {
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right)
+                        .all(|(left, right)| left.approx_eq(right, epsilon))
+            }
+            _ => false,
         }
     }
 }
 
-impl ASTVisitor for ASTEvaluator {
-    fn visit_statement(&mut self, statement: &ASTStatement) {
-        ASTVisitor::do_visit_statement(self, statement);
+impl ASTExpression {
+    fn approx_eq(&self, other: &ASTExpression, epsilon: f64) -> bool {
+        match (&self.kind, &other.kind) {
+            (ASTExpressionKind::Number(left), ASTExpressionKind::Number(right)) => {
+                left.unit == right.unit
+                    && left.percent == right.percent
+                    && (left.number.to_bits() == right.number.to_bits()
+                        || (left.number - right.number).abs() <= epsilon)
+            }
+            (ASTExpressionKind::Boolean(left), ASTExpressionKind::Boolean(right)) => left == right,
+            (ASTExpressionKind::Variable(left), ASTExpressionKind::Variable(right)) => {
+                left.name == right.name
+            }
+            (ASTExpressionKind::Binary(left), ASTExpressionKind::Binary(right)) => {
+                left.operator.kind == right.operator.kind
+                    && left.left.approx_eq(&right.left, epsilon)
+                    && left.right.approx_eq(&right.right, epsilon)
+            }
+            (ASTExpressionKind::Parenthesized(left), ASTExpressionKind::Parenthesized(right)) => {
+                left.expression.approx_eq(&right.expression, epsilon)
+            }
+            (ASTExpressionKind::StartEnd(left), ASTExpressionKind::StartEnd(right)) => {
+                left.statements.len() == right.statements.len()
+                    && left
+                        .statements
+                        .iter()
+                        .zip(&right.statements)
+                        .all(|(left, right)| left.approx_eq(right, epsilon))
+            }
+            (ASTExpressionKind::Absolute(left), ASTExpressionKind::Absolute(right)) => {
+                left.expression.approx_eq(&right.expression, epsilon)
+            }
+            (ASTExpressionKind::Unary(left), ASTExpressionKind::Unary(right)) => {
+                left.operator == right.operator && left.operand.approx_eq(&right.operand, epsilon)
+            }
+            (ASTExpressionKind::Factorial(left), ASTExpressionKind::Factorial(right)) => {
+                left.expression.approx_eq(&right.expression, epsilon)
+            }
+            (ASTExpressionKind::Conditional(left), ASTExpressionKind::Conditional(right)) => {
+                left.condition.approx_eq(&right.condition, epsilon)
+                    && left.then_branch.approx_eq(&right.then_branch, epsilon)
+                    && left.else_branch.approx_eq(&right.else_branch, epsilon)
+            }
+            (ASTExpressionKind::Call(left), ASTExpressionKind::Call(right)) => {
+                left.name == right.name
+                    && left.arguments.len() == right.arguments.len()
+                    && left
+                        .arguments
+                        .iter()
+                        .zip(&right.arguments)
+                        .all(|(left, right)| left.approx_eq(right, epsilon))
+            }
+            _ => false,
+        }
     }
+}
 
-    fn visit_expression(&mut self, expression: &ASTExpression) {
-        match &expression.kind {
+// Structural equality, ignoring spans, so the same subtree written in two
+// places compares equal. A `NaN` literal equals itself.
+impl PartialEq for ASTExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.approx_eq(other, 0.0)
+    }
+}
+
+impl Eq for ASTExpression {}
+
+impl Hash for ASTExpression {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        core::mem::discriminant(&self.kind).hash(state);
+        match &self.kind {
             ASTExpressionKind::Number(number) => {
-                self.visit_number(number);
+                // `0.0` and `-0.0` compare equal, so they must hash alike.
+                let value = if number.number == 0.0 {
+                    0.0
+                } else {
+                    number.number
+                };
+                value.to_bits().hash(state);
+                number.unit.hash(state);
+                number.percent.hash(state);
             }
-            ASTExpressionKind::Binary(expr) => {
-                self.visit_binary_expression(expr);
+            ASTExpressionKind::Boolean(value) => value.hash(state),
+            ASTExpressionKind::Variable(variable) => variable.name.hash(state),
+            ASTExpressionKind::Binary(binary_expression) => {
+                binary_expression.operator.kind.hash(state);
+                binary_expression.left.hash(state);
+                binary_expression.right.hash(state);
             }
-            ASTExpressionKind::Parenthesized(expr) => {
-                self.visit_parenthesized_expression(expr);
+            ASTExpressionKind::Unary(unary_expression) => {
+                unary_expression.operator.hash(state);
+                unary_expression.operand.hash(state);
             }
-            ASTExpressionKind::StartEnd(expr) => {
-                self.visit_start_end_expression(expr);
+            ASTExpressionKind::Call(call) => {
+                call.name.hash(state);
+                call.arguments.hash(state);
+            }
+            // Statements have no hash of their own; equality still compares
+            // the blocks in full.
+            ASTExpressionKind::StartEnd(block) => block.statements.len().hash(state),
+            ASTExpressionKind::Parenthesized(_)
+            | ASTExpressionKind::Absolute(_)
+            | ASTExpressionKind::Factorial(_)
+            | ASTExpressionKind::Conditional(_) => {
+                for child in self.children() {
+                    child.hash(state);
+                }
+            }
+        }
+    }
+}
+
+// Shorthand constructors for building trees in code, e.g.
+// `ASTExpression::add(ASTExpression::number(1.0), ASTExpression::number(2.0))`.
+#[allow(clippy::should_implement_trait)]
+impl ASTExpression {
+    pub fn add(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::Plus, left, right)
+    }
+
+    pub fn sub(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::Minus, left, right)
+    }
+
+    pub fn mul(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::Multiply, left, right)
+    }
+
+    pub fn div(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::Divide, left, right)
+    }
+
+    pub fn pow(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::Pow, left, right)
+    }
+
+    pub fn integer_divide(left: ASTExpression, right: ASTExpression) -> Self {
+        Self::synthetic_binary(ASTBinaryOperatorKind::IntegerDivide, left, right)
+    }
+
+    fn synthetic_binary(
+        kind: ASTBinaryOperatorKind,
+        left: ASTExpression,
+        right: ASTExpression,
+    ) -> Self {
+        ASTExpression::binary(ASTBinaryOperator::synthetic(kind), left, right)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    EmptyProgram,
+    UndefinedVariable {
+        name: String,
+        span: TextSpan,
+    },
+    DivisionByZero,
+    NonIntegerOperand,
+    Overflow,
+    UndefinedFunction(String),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    RecursionLimit,
+    TypeMismatch,
+    // A custom operator such as `@` was used with no handler registered.
+    UnregisteredOperator(String),
+    // Units as written, e.g. `m*s^-1`; `1` for a plain number.
+    UnitMismatch {
+        left: String,
+        right: String,
+    },
+    // Another error together with the source of the expression that raised
+    // it; only produced when `ASTEvaluator::error_source` is set.
+    InExpression {
+        error: Box<EvalError>,
+        source: String,
+    },
+    // An RPN sequence ran out of operands or left more than one value.
+    MalformedRpn,
+    // A `{ }` block with no statement that produces a value.
+    EmptyBlock,
+    // Integer mode only: the exact result is a fraction, as for `2 ^ -1`.
+    NonIntegerResult,
+    // NaN or an infinity turned up while `strict_finite` is set.
+    NonFinite,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::EmptyProgram => write!(f, "empty program"),
+            EvalError::UndefinedVariable { name, .. } => {
+                write!(f, "undefined variable `{}`", name)
+            }
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::NonIntegerOperand => write!(f, "non-integer operand"),
+            EvalError::Overflow => write!(f, "overflow"),
+            EvalError::UndefinedFunction(name) => write!(f, "undefined function `{}`", name),
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` takes {} argument(s) but was given {}",
+                name, expected, found
+            ),
+            EvalError::RecursionLimit => write!(f, "recursion limit reached"),
+            EvalError::TypeMismatch => write!(f, "type mismatch"),
+            EvalError::UnregisteredOperator(symbol) => {
+                write!(f, "no handler registered for `{}`", symbol)
             }
-            ASTExpressionKind::Variable(variable_name) => {
-                let variable_expression = ASTVariableExpression::new(variable_name.clone());
-                if (variable_name == "x") {
-                    self.last_value = Some(1.0 as f64);
-                } else if (variable_name == "y") {
-                    self.last_value = Some(3.0 as f64);
+            EvalError::UnitMismatch { left, right } => {
+                write!(f, "unit mismatch between `{}` and `{}`", left, right)
+            }
+            EvalError::InExpression { error, source } => write!(f, "{} in `{}`", error, source),
+            EvalError::MalformedRpn => write!(f, "malformed RPN"),
+            EvalError::EmptyBlock => write!(f, "empty block"),
+            EvalError::NonIntegerResult => write!(f, "result is not an integer"),
+            EvalError::NonFinite => write!(f, "non-finite value"),
+        }
+    }
+}
+
+impl core::error::Error for EvalError {}
+
+// One binary operation performed during evaluation, recorded in the order
+// the evaluator reduced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep<N: Number = f64> {
+    pub operator: ASTBinaryOperatorKind,
+    pub left: N,
+    pub right: N,
+    pub result: Value<N>,
+}
+
+// The arithmetic `ASTEvaluator` is generic over. Unless a type overrides
+// `from_parsed_literal`, literals are handed to `from_literal` in their
+// shortest decimal spelling, so a decimal type sees `0.1` rather than the
+// nearest binary fraction.
+//
+// Operations without a method here (factorial, `//` flooring, `^` on bits,
+// the built-in constants and overflow checks) go through `f64`.
+pub trait Number: Clone + PartialOrd + fmt::Debug + fmt::Display + 'static {
+    fn from_literal(literal: &str) -> Self;
+    fn from_f64(value: f64) -> Self;
+    // A literal the lexer has already parsed to `value`. Types that hold
+    // `value` exactly can skip the round trip through its spelling.
+    fn from_parsed_literal(value: f64) -> Self {
+        Self::from_literal(&value.to_string())
+    }
+    fn to_f64(&self) -> f64;
+    fn add(&self, other: &Self) -> Self;
+    fn sub(&self, other: &Self) -> Self;
+    fn mul(&self, other: &Self) -> Self;
+    fn div(&self, other: &Self) -> Self;
+    fn pow(&self, exponent: &Self) -> Self;
+
+    fn neg(&self) -> Self {
+        Self::from_f64(0.0).sub(self)
+    }
+}
+
+macro_rules! impl_float_number {
+    ($float:ty) => {
+        impl Number for $float {
+            fn from_literal(literal: &str) -> Self {
+                literal.parse().unwrap_or(0.0)
+            }
+            fn from_f64(value: f64) -> Self {
+                value as $float
+            }
+            fn from_parsed_literal(value: f64) -> Self {
+                let narrowed = value as $float;
+                if narrowed as f64 == value {
+                    narrowed
                 } else {
-                    self.visit_variable(&variable_expression);
+                    Self::from_literal(&value.to_string())
                 }
             }
+            fn to_f64(&self) -> f64 {
+                *self as f64
+            }
+            fn add(&self, other: &Self) -> Self {
+                self + other
+            }
+            fn sub(&self, other: &Self) -> Self {
+                self - other
+            }
+            fn mul(&self, other: &Self) -> Self {
+                self * other
+            }
+            fn div(&self, other: &Self) -> Self {
+                self / other
+            }
+            fn pow(&self, exponent: &Self) -> Self {
+                self.powf(*exponent)
+            }
+            fn neg(&self) -> Self {
+                -self
+            }
+        }
+    };
+}
+
+impl_float_number!(f64);
+impl_float_number!(f32);
+
+// Comparisons produce `Bool`, arithmetic only accepts `Number`. `Error` is
+// only used for statement outcomes when errors are propagated as values.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value<N: Number = f64> {
+    Number(N),
+    Bool(bool),
+    Error(EvalError),
+}
+
+impl<N: Number> Value<N> {
+    pub fn into_number(self) -> Result<N, EvalError> {
+        match self {
+            Value::Number(number) => Ok(number),
+            Value::Bool(_) => Err(EvalError::TypeMismatch),
+            Value::Error(error) => Err(error),
+        }
+    }
+}
+
+impl<N: Number> From<N> for Value<N> {
+    fn from(number: N) -> Self {
+        Value::Number(number)
+    }
+}
+
+impl<N: Number> From<bool> for Value<N> {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl<N: Number> fmt::Display for Value<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Error(error) => write!(f, "error: {}", error),
+        }
+    }
+}
+
+// Bound to the value of the most recent statement that produced one.
+pub const ANSWER_VARIABLE: &str = "ans";
+
+const DEFAULT_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+pub type OperationHook<N = f64> = Box<dyn FnMut(&ASTBinaryOperatorKind, N, N, &Value<N>)>;
+
+pub type CustomOperator<N = f64> = Box<dyn Fn(N, N) -> N>;
+
+// `ASTEvaluator::new()` evaluates with `f64`; other number types are picked
+// with `ASTEvaluator::<f32>::default()`.
+pub struct ASTEvaluator<'a, N: Number = f64> {
+    pub last_value: Option<Value<N>>,
+    // Innermost scope last; the first entry holds the globals and is never
+    // popped.
+    scopes: Vec<HashMap<String, Value<N>>>,
+    pub functions: HashMap<String, Rc<ASTFunctionDeclaration>>,
+    pub error: Option<EvalError>,
+    // Reject `//` when either operand has a fractional part instead of
+    // flooring the quotient.
+    pub strict_integer_divide: bool,
+    // Report finite operands overflowing to infinity instead of returning `inf`.
+    pub checked_overflow: bool,
+    // Reject NaN and infinities outright: the `nan()` and `inf()` built-ins
+    // fail, as does any operation whose result is not finite.
+    pub strict_finite: bool,
+    // Bounds nested function calls so runaway recursion reports an error
+    // instead of overflowing the stack.
+    pub max_call_depth: usize,
+    call_depth: usize,
+    // Every reduction is appended here while tracing is enabled.
+    pub trace: Option<Vec<TraceStep<N>>>,
+    // Let an error poison only the statement it occurs in: both operands of a
+    // failing operation are still evaluated and later statements still run.
+    pub propagate_errors: bool,
+    // Called after every binary operation that succeeds, with the operator,
+    // both operands and the result.
+    pub on_operation: Option<OperationHook<N>>,
+    // Wrap the first error in `EvalError::InExpression`, naming the
+    // innermost expression that failed.
+    pub error_source: bool,
+    // State of the generator behind `rand()`; see `with_seed`.
+    random_state: u64,
+    // Handlers for custom operators, keyed by their symbol.
+    custom_operators: HashMap<String, CustomOperator<N>>,
+    // Every value printed by a `print` statement, across runs.
+    pub output: Vec<Value<N>>,
+    // Read-only variables looked up after every scope, so a caller's map can
+    // be used without copying it in. Declarations bind in the evaluator's
+    // own scopes and shadow them instead of writing through.
+    pub borrowed: Option<&'a HashMap<String, N>>,
+}
+
+impl<N: Number> Default for ASTEvaluator<'_, N> {
+    fn default() -> Self {
+        Self {
+            last_value: None,
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            error: None,
+            strict_integer_divide: false,
+            checked_overflow: false,
+            strict_finite: false,
+            max_call_depth: 128,
+            call_depth: 0,
+            trace: None,
+            propagate_errors: false,
+            on_operation: None,
+            error_source: false,
+            random_state: DEFAULT_SEED,
+            custom_operators: HashMap::new(),
+            output: Vec::new(),
+            borrowed: None,
+        }
+    }
+}
+
+impl ASTEvaluator<'_> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `rand()` gives the same sequence for the same seed; without one it
+    // starts from a fixed default.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            // xorshift never leaves zero.
+            random_state: if seed == 0 { DEFAULT_SEED } else { seed },
+            ..Self::default()
+        }
+    }
+}
+
+impl<N: Number> ASTEvaluator<'_, N> {
+    // Runs every statement of `ast`, keeping the variable bindings so the same
+    // evaluator can be reused across runs.
+    pub fn evaluate(&mut self, ast: &Ast) -> Result<N, EvalError> {
+        self.evaluate_value(ast).and_then(Value::into_number)
+    }
+
+    pub fn evaluate_value(&mut self, ast: &Ast) -> Result<Value<N>, EvalError> {
+        // Units only constrain which operations are allowed; the arithmetic
+        // itself ignores them.
+        UnitChecker::check(ast)?;
+        self.last_value = None;
+        self.error = None;
+        self.call_depth = 0;
+        for statement in &ast.statements {
+            if self.propagate_errors {
+                self.last_value = None;
+                self.error = None;
+            }
+            self.visit_statement(statement);
+            if self.error.is_some() && !self.propagate_errors {
+                break;
+            }
+        }
+        self.value_result()
+    }
+
+    // Evaluates every statement with errors propagated as values, returning
+    // the outcome of each expression statement. A unit mismatch anywhere
+    // in the program is reported for every one of them.
+    pub fn evaluate_values(&mut self, ast: &Ast) -> Vec<Value<N>> {
+        if let Err(error) = UnitChecker::check(ast) {
+            return ast
+                .statements
+                .iter()
+                .filter(|statement| matches!(statement.kind, ASTStatementKind::Expression(_)))
+                .map(|_| Value::Error(error.clone()))
+                .collect();
+        }
+        let propagate_errors = core::mem::replace(&mut self.propagate_errors, true);
+        self.call_depth = 0;
+        let mut values = Vec::new();
+        for statement in &ast.statements {
+            self.last_value = None;
+            self.error = None;
+            self.visit_statement(statement);
+            if let ASTStatementKind::Expression(_) = statement.kind {
+                values.push(self.value_result().unwrap_or_else(Value::Error));
+            }
+        }
+        self.propagate_errors = propagate_errors;
+        values
+    }
+
+    // Like evaluate, but collects the value of every expression statement
+    // instead of only the last one.
+    pub fn evaluate_all(&mut self, ast: &Ast) -> Result<Vec<N>, EvalError> {
+        UnitChecker::check(ast)?;
+        self.last_value = None;
+        self.error = None;
+        self.call_depth = 0;
+        let mut values = Vec::new();
+        for statement in &ast.statements {
+            self.visit_statement(statement);
+            if let Some(error) = &self.error {
+                return Err(error.clone());
+            }
+            if let (ASTStatementKind::Expression(_), Some(value)) =
+                (&statement.kind, &self.last_value)
+            {
+                values.push(value.clone().into_number()?);
+            }
+        }
+        Ok(values)
+    }
+
+    // Snapshots the global numeric variables, `ans` included, after every
+    // statement. Variables holding a `Bool` are left out of the snapshots.
+    pub fn evaluate_history(&mut self, ast: &Ast) -> Result<Vec<HashMap<String, N>>, EvalError> {
+        UnitChecker::check(ast)?;
+        self.last_value = None;
+        self.error = None;
+        self.call_depth = 0;
+        let mut history = Vec::new();
+        for statement in &ast.statements {
+            self.visit_statement(statement);
+            if let Some(error) = &self.error {
+                return Err(error.clone());
+            }
+            let snapshot = self
+                .variables()
+                .iter()
+                .filter_map(|(name, value)| match value {
+                    Value::Number(number) => Some((name.clone(), number.clone())),
+                    _ => None,
+                })
+                .collect();
+            history.push(snapshot);
+        }
+        Ok(history)
+    }
+
+    pub fn result(&self) -> Result<N, EvalError> {
+        self.value_result().and_then(Value::into_number)
+    }
+
+    pub fn value_result(&self) -> Result<Value<N>, EvalError> {
+        match (&self.error, &self.last_value) {
+            (Some(error), _) => Err(error.clone()),
+            (None, Some(value)) => Ok(value.clone()),
+            (None, None) => Err(EvalError::EmptyProgram),
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Value<N>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // Gives the custom operator `symbol` (currently only `@`) its meaning,
+    // replacing any earlier handler.
+    pub fn register_operator(&mut self, symbol: &str, handler: impl Fn(N, N) -> N + 'static) {
+        self.custom_operators
+            .insert(symbol.to_string(), Box::new(handler));
+    }
+
+    // Binds `name` in the innermost scope, shadowing any outer binding.
+    pub fn define(&mut self, name: String, value: impl Into<Value<N>>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, value.into());
+        }
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Value<N>> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
+
+    // The global bindings, including `ans`.
+    pub fn variables(&self) -> &HashMap<String, Value<N>> {
+        &self.scopes[0]
+    }
+
+    fn globals_mut(&mut self) -> &mut HashMap<String, Value<N>> {
+        &mut self.scopes[0]
+    }
+
+    // Functions the program can call without defining them, though a
+    // definition of the same name takes precedence. `nan()`, `inf()` and
+    // `eps()` are for probing numeric edge cases; `rand()` is uniform in
+    // `[0, 1)` and `rand(lo, hi)` in `[lo, hi)`. `safediv(a, b, default)` is
+    // `a / b`, or `default` where that would divide by zero.
+    fn is_builtin(&self, name: &str) -> bool {
+        matches!(name, "nan" | "inf" | "eps" | "rand" | "safediv")
+            && !self.functions.contains_key(name)
+    }
+
+    fn finish_builtin(&mut self, call: &ASTCallExpression, arguments: Vec<N>) {
+        let value = match (call.name.as_str(), arguments.as_slice()) {
+            ("nan", []) => N::from_f64(f64::NAN),
+            ("inf", []) => N::from_f64(f64::INFINITY),
+            ("eps", []) => N::from_f64(f64::EPSILON),
+            ("rand", []) => N::from_f64(self.next_random()),
+            ("rand", [low, high]) => {
+                let fraction = N::from_f64(self.next_random());
+                low.add(&high.sub(low).mul(&fraction))
+            }
+            ("safediv", [_, divisor, default]) if *divisor == N::from_f64(0.0) => default.clone(),
+            ("safediv", [dividend, divisor, _]) => dividend.div(divisor),
+            (name, _) => {
+                self.fail(EvalError::ArityMismatch {
+                    name: name.to_string(),
+                    expected: match name {
+                        "rand" => 2,
+                        "safediv" => 3,
+                        _ => 0,
+                    },
+                    found: arguments.len(),
+                });
+                return;
+            }
+        };
+        if self.strict_finite && !value.to_f64().is_finite() {
+            self.fail(EvalError::NonFinite);
+            return;
+        }
+        self.last_value = Some(Value::Number(value));
+    }
+
+    // xorshift64*, taking the top 53 bits as the fraction.
+    fn next_random(&mut self) -> f64 {
+        let mut state = self.random_state;
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        self.random_state = state;
+        (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn constant(name: &str) -> Option<N> {
+        match name {
+            "pi" => Some(N::from_f64(core::f64::consts::PI)),
+            "e" => Some(N::from_f64(core::f64::consts::E)),
+            _ => None,
+        }
+    }
+
+    // The numeric value of the last evaluated operand; a `Bool` is a type
+    // error.
+    fn operand(&mut self) -> Option<N> {
+        match &self.last_value {
+            Some(Value::Number(number)) => Some(number.clone()),
+            Some(_) => {
+                self.fail(EvalError::TypeMismatch);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn fail(&mut self, error: EvalError) {
+        self.last_value = None;
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl<N: Number> ASTVisitor for ASTEvaluator<'_, N> {
+    fn visit_statement(&mut self, statement: &ASTStatement) {
+        ASTVisitor::do_visit_statement(self, statement);
+        self.bind_answer();
+    }
+
+    fn visit_expression(&mut self, expression: &ASTExpression) {
+        let had_error = self.error.is_some();
+        ASTVisitor::do_visit_expression(self, expression);
+        self.attach_source(expression, had_error);
+    }
+
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.visit_expression(&declaration.initializer);
+        self.finish_variable_declaration(declaration);
+    }
+
+    // Statements inside a block do not rebind `ans`; only the block's
+    // value, as part of the enclosing statement, does.
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        self.enter_block();
+        for statement in &start_end_expression.statements {
+            ASTVisitor::do_visit_statement(self, statement);
+            if self.error.is_some() {
+                break;
+            }
+        }
+        self.leave_block();
+    }
+
+    // The statement's value is the last one printed, so `ans` follows it.
+    fn visit_print(&mut self, arguments: &[ASTExpression]) {
+        for argument in arguments {
+            self.visit_expression(argument);
+            if !self.finish_print() {
+                return;
+            }
         }
     }
 
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        self.functions
+            .insert(declaration.name.clone(), Rc::new(declaration.clone()));
+    }
+
     fn visit_number(&mut self, number: &ASTNumberExpression) {
-        self.last_value = Some(number.number as f64);
+        let value = N::from_parsed_literal(number.number);
+        let value = if number.percent {
+            value.div(&N::from_f64(100.0))
+        } else {
+            value
+        };
+        self.last_value = Some(Value::Number(value));
+    }
+
+    fn visit_boolean(&mut self, value: bool) {
+        self.last_value = Some(Value::Bool(value));
     }
     fn visit_variable(&mut self, variable: &ASTVariableExpression) {
-        self.last_value = self.variables.get(&variable.name).cloned();
+        // User bindings shadow the built-in constants.
+        let value = self
+            .lookup(&variable.name)
+            .cloned()
+            .or_else(|| {
+                let borrowed = self.borrowed?.get(&variable.name)?;
+                Some(Value::Number(borrowed.clone()))
+            })
+            .or_else(|| Self::constant(&variable.name).map(Value::Number));
+        match value {
+            Some(value) => self.last_value = Some(value),
+            None => self.fail(EvalError::UndefinedVariable {
+                name: variable.name.clone(),
+                span: variable.span.clone(),
+            }),
+        }
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.visit_expression(&absolute_expression.expression);
+        self.finish_absolute();
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.visit_expression(&unary_expression.operand);
+        self.finish_unary(&unary_expression.operator);
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+        self.finish_factorial();
+    }
+
+    // Only the chosen branch is evaluated.
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.visit_expression(&conditional.condition);
+        match self.condition() {
+            Some(true) => self.visit_expression(&conditional.then_branch),
+            Some(false) => self.visit_expression(&conditional.else_branch),
+            None => {}
+        }
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        if self.is_builtin(&call.name) {
+            let mut arguments = Vec::new();
+            for argument in &call.arguments {
+                self.visit_expression(argument);
+                let Some(value) = self.operand() else {
+                    return;
+                };
+                arguments.push(value);
+            }
+            self.finish_builtin(call, arguments);
+            return;
+        }
+        let Some(function) = self.callee(call) else {
+            return;
+        };
+
+        let mut scope = HashMap::new();
+        for (parameter, argument) in function.parameters.iter().zip(&call.arguments) {
+            self.visit_expression(argument);
+            let Some(value) = self.last_value.clone() else {
+                return;
+            };
+            scope.insert(parameter.name.clone(), value);
+        }
+
+        let caller_scopes = self.enter_call(scope);
+        self.visit_expression(&function.body);
+        self.leave_call(caller_scopes);
     }
 
     fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
         self.visit_expression(&binary_expression.left);
-        let left = self.last_value.unwrap();
-        print!("{}", left);
+        let left = self.operand();
+        if left.is_none() && !self.propagate_errors {
+            return;
+        }
+        // When propagating, the right operand still runs so its reductions
+        // are traced; the left error wins.
+        let left_error = self.error.take();
         self.visit_expression(&binary_expression.right);
-        let right = self.last_value.unwrap();
-
-        self.last_value = Some(match binary_expression.operator.kind {
-            ASTBinaryOperatorKind::Plus => left + right,
-            ASTBinaryOperatorKind::Minus => left - right,
-            ASTBinaryOperatorKind::Multiply => left * right,
-            ASTBinaryOperatorKind::Divide => left / right,
-            ASTBinaryOperatorKind::Pow => left.powf((right as u32).into()),
-            ASTBinaryOperatorKind::IntegerDivide => left / right,
-            ASTBinaryOperatorKind::Eof => 0.0,
-            _ => panic!("Unhandled binary operator kind"),
-        });
+        if binary_expression.is_percent_change() {
+            self.scale_percent(&left);
+        }
+        self.finish_binary(&binary_expression.operator.kind, left, left_error);
+    }
+}
+
+// The steps that complete each node once its operands have been evaluated,
+// shared by the recursive visitor and `evaluate_iterative`.
+impl<N: Number> ASTEvaluator<'_, N> {
+    // The innermost expression to finish with an error it did not start with
+    // is the one that raised it.
+    fn attach_source(&mut self, expression: &ASTExpression, had_error: bool) {
+        if !self.error_source || had_error {
+            return;
+        }
+        if let Some(error) = self.error.take() {
+            self.error = Some(match error {
+                EvalError::InExpression { .. } => error,
+                error => EvalError::InExpression {
+                    error: Box::new(error),
+                    source: ASTSourcePrinter::render_expression(expression),
+                },
+            });
+        }
+    }
+
+    fn bind_answer(&mut self) {
+        if let Some(value) = self.last_value.clone() {
+            self.globals_mut()
+                .insert(ANSWER_VARIABLE.to_string(), value);
+        }
+    }
+
+    fn finish_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        let Some(value) = self.last_value.clone() else {
+            return;
+        };
+        if declaration.assignment {
+            if let Some(binding) = self.lookup_mut(&declaration.name) {
+                *binding = value;
+                return;
+            }
+        }
+        self.define(declaration.name.clone(), value);
+    }
+
+    fn enter_block(&mut self) {
+        self.push_scope();
+        self.last_value = None;
+    }
+
+    // A block whose statements produced no value, such as `{}` or one that
+    // only declares a function, is an error rather than a silent gap.
+    fn leave_block(&mut self) {
+        self.pop_scope();
+        if self.last_value.is_none() && self.error.is_none() {
+            self.fail(EvalError::EmptyBlock);
+        }
+    }
+
+    // Returns false when the argument failed and printing should stop.
+    fn finish_print(&mut self) -> bool {
+        match &self.last_value {
+            Some(value) => {
+                self.output.push(value.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn finish_absolute(&mut self) {
+        if let Some(value) = self.operand() {
+            let absolute = if value < N::from_f64(0.0) {
+                value.neg()
+            } else {
+                value
+            };
+            self.last_value = Some(Value::Number(absolute));
+        }
+    }
+
+    fn finish_unary(&mut self, operator: &ASTUnaryOperatorKind) {
+        let Some(value) = self.operand() else {
+            return;
+        };
+        let result = match operator {
+            ASTUnaryOperatorKind::Neg => value.neg(),
+        };
+        self.last_value = Some(Value::Number(result));
+    }
+
+    // Multiplies up iteratively; `170!` is the largest factorial an f64 can
+    // hold, so the product turning infinite is reported as an overflow.
+    fn finish_factorial(&mut self) {
+        let Some(value) = self.operand().map(|value| value.to_f64()) else {
+            return;
+        };
+        if value < 0.0 || value.fract() != 0.0 {
+            self.fail(EvalError::NonIntegerOperand);
+            return;
+        }
+        let mut product = 1.0;
+        let mut factor = 2.0;
+        while factor <= value {
+            product *= factor;
+            if product.is_infinite() {
+                self.fail(EvalError::Overflow);
+                return;
+            }
+            factor += 1.0;
+        }
+        self.last_value = Some(Value::Number(N::from_f64(product)));
+    }
+
+    // Which branch of a conditional to take; numbers count as true when
+    // non-zero.
+    fn condition(&self) -> Option<bool> {
+        match &self.last_value {
+            Some(Value::Bool(value)) => Some(*value),
+            Some(Value::Number(number)) => Some(*number != N::from_f64(0.0)),
+            _ => None,
+        }
+    }
+
+    // The function `call` refers to, once it is known to exist, to take
+    // this many arguments and to fit within the call depth limit.
+    fn callee(&mut self, call: &ASTCallExpression) -> Option<Rc<ASTFunctionDeclaration>> {
+        let Some(function) = self.functions.get(&call.name).cloned() else {
+            self.fail(EvalError::UndefinedFunction(call.name.clone()));
+            return None;
+        };
+        if function.parameters.len() != call.arguments.len() {
+            self.fail(EvalError::ArityMismatch {
+                name: call.name.clone(),
+                expected: function.parameters.len(),
+                found: call.arguments.len(),
+            });
+            return None;
+        }
+        if self.call_depth >= self.max_call_depth {
+            self.fail(EvalError::RecursionLimit);
+            return None;
+        }
+        Some(function)
+    }
+
+    // The body sees the globals and its own parameters, not the locals of
+    // whoever called it. Returns the caller's scopes for `leave_call`.
+    fn enter_call(
+        &mut self,
+        parameters: HashMap<String, Value<N>>,
+    ) -> Vec<HashMap<String, Value<N>>> {
+        let caller_scopes = self.scopes.split_off(1);
+        self.scopes.push(parameters);
+        self.call_depth += 1;
+        caller_scopes
+    }
+
+    fn leave_call(&mut self, caller_scopes: Vec<HashMap<String, Value<N>>>) {
+        self.call_depth -= 1;
+        self.scopes.truncate(1);
+        self.scopes.extend(caller_scopes);
+    }
+
+    // Called with the right operand just evaluated. `left` is `None` when the
+    // left operand failed, in which case `left_error` holds its error.
+    // Turns the fraction a `%` literal evaluated to into that share of the
+    // left operand, so `+` and `-` then add or take it off.
+    fn scale_percent(&mut self, left: &Option<N>) {
+        if let (Some(left), Some(Value::Number(fraction))) = (left, &self.last_value) {
+            self.last_value = Some(Value::Number(left.mul(fraction)));
+        }
+    }
+
+    fn finish_binary(
+        &mut self,
+        operator: &ASTBinaryOperatorKind,
+        left: Option<N>,
+        left_error: Option<EvalError>,
+    ) {
+        if let Some(error) = left_error {
+            self.error = Some(error);
+            self.last_value = None;
+            return;
+        }
+        let (Some(left), Some(right)) = (left, self.operand()) else {
+            return;
+        };
+
+        let is_division = matches!(
+            operator,
+            ASTBinaryOperatorKind::Divide | ASTBinaryOperatorKind::IntegerDivide
+        );
+        if is_division && right == N::from_f64(0.0) {
+            self.fail(EvalError::DivisionByZero);
+            return;
+        }
+        if self.strict_integer_divide
+            && matches!(operator, ASTBinaryOperatorKind::IntegerDivide)
+            && (left.to_f64().fract() != 0.0 || right.to_f64().fract() != 0.0)
+        {
+            self.fail(EvalError::NonIntegerOperand);
+            return;
+        }
+
+        let result = match operator {
+            ASTBinaryOperatorKind::Plus => Value::Number(left.add(&right)),
+            ASTBinaryOperatorKind::Minus => Value::Number(left.sub(&right)),
+            ASTBinaryOperatorKind::Multiply => Value::Number(left.mul(&right)),
+            ASTBinaryOperatorKind::Divide => Value::Number(left.div(&right)),
+            ASTBinaryOperatorKind::Pow => Value::Number(left.pow(&right)),
+            ASTBinaryOperatorKind::IntegerDivide => {
+                Value::Number(N::from_f64(left.div(&right).to_f64().floor()))
+            }
+            ASTBinaryOperatorKind::Less => Value::Bool(left < right),
+            ASTBinaryOperatorKind::LessEqual => Value::Bool(left <= right),
+            ASTBinaryOperatorKind::Greater => Value::Bool(left > right),
+            ASTBinaryOperatorKind::GreaterEqual => Value::Bool(left >= right),
+            ASTBinaryOperatorKind::Equal => Value::Bool(left == right),
+            ASTBinaryOperatorKind::NotEqual => Value::Bool(left != right),
+            ASTBinaryOperatorKind::BitXor => Value::Number(N::from_f64(
+                ((left.to_f64() as i64) ^ (right.to_f64() as i64)) as f64,
+            )),
+            ASTBinaryOperatorKind::At => {
+                let symbol = operator.to_string();
+                let Some(handler) = self.custom_operators.get(&symbol) else {
+                    self.fail(EvalError::UnregisteredOperator(symbol));
+                    return;
+                };
+                Value::Number(handler(left.clone(), right.clone()))
+            }
+        };
+        if self.strict_finite
+            && matches!(&result, Value::Number(result) if !result.to_f64().is_finite())
+        {
+            self.fail(EvalError::NonFinite);
+            return;
+        }
+        if self.checked_overflow
+            && matches!(&result, Value::Number(result) if result.to_f64().is_infinite())
+            && left.to_f64().is_finite()
+            && right.to_f64().is_finite()
+        {
+            self.fail(EvalError::Overflow);
+            return;
+        }
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceStep {
+                operator: operator.clone(),
+                left: left.clone(),
+                right: right.clone(),
+                result: result.clone(),
+            });
+        }
+        if let Some(on_operation) = &mut self.on_operation {
+            on_operation(operator, left, right, &result);
+        }
+        self.last_value = Some(result);
     }
 }
@@ -1,9 +1,96 @@
-use crate::ast::lexer::{Token, TokenKind};
-use crate::ast::{ASTBinaryOperator, ASTBinaryOperatorKind, ASTExpression, ASTStatement};
+use crate::ast::lexer::{LexError, Lexer, TextSpan, Token, TokenKind};
+use crate::ast::{
+    ASTBinaryOperator, ASTBinaryOperatorKind, ASTExpression, ASTStatement, ASTUnaryOperatorKind,
+    ASTVariableExpression,
+};
+use crate::prelude::*;
+use core::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    UnexpectedToken(Token),
+    UnexpectedEof,
+    MissingRightOperand(TextSpan),
+    BadToken(TextSpan),
+    // A closing or separating token such as `)`, `=` or `then` was missing.
+    Expected { expected: TokenKind, found: Token },
+    // Nesting beyond `Parser::max_nesting`, reported at the token that
+    // would have opened one more level.
+    TooDeep(TextSpan),
+    // Input left over after `parse_single_expression`; the span is that of
+    // the first leftover token.
+    TrailingTokens(TextSpan),
+    // One more statement followed the first `Parser::max_statements`.
+    TooManyStatements,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(token) => write!(
+                f,
+                "unexpected `{}` at {}",
+                token.span.literal, token.span.start
+            ),
+            ParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+            ParseError::MissingRightOperand(span) => write!(
+                f,
+                "`{}` at {} is missing its right operand",
+                span.literal, span.start
+            ),
+            ParseError::BadToken(span) => {
+                write!(f, "invalid token `{}` at {}", span.literal, span.start)
+            }
+            ParseError::Expected { expected, found } => write!(
+                f,
+                "expected {} but found `{}` at {}",
+                expected.name(),
+                found.span.literal,
+                found.span.start
+            ),
+            ParseError::TrailingTokens(span) => write!(
+                f,
+                "unexpected `{}` at {} after the expression",
+                span.literal, span.start
+            ),
+            ParseError::TooDeep(span) => {
+                write!(
+                    f,
+                    "nesting too deep at `{}` at {}",
+                    span.literal, span.start
+                )
+            }
+            ParseError::TooManyStatements => write!(f, "too many statements"),
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<ParseError>,
+    // For each statement returned so far, the source offset where it ends
+    // and where the token that ended it ends; see `reparse_from`.
+    statement_bounds: Vec<(usize, usize)>,
+    // Read `=` inside an expression as `==`, as in written maths. A
+    // statement that starts with `var` or `name =` is still a declaration.
+    pub equals_is_equality: bool,
+    // Binding strength of `@`, on the same scale as
+    // `ASTBinaryOperator::precedence`: 3 groups it with `+`, 4 with `*`.
+    pub at_precedence: u8,
+    // Pocket-calculator order: every binary operator binds equally and
+    // groups to the left, so `2 + 3 * 4` is `(2 + 3) * 4`.
+    pub flat_precedence: bool,
+    // How deeply parentheses, blocks, operands of `-` and the like may nest.
+    // Parsing recurses once per level, so this keeps hostile input from
+    // overflowing the stack.
+    pub max_nesting: usize,
+    depth: usize,
+    // How many statements `next_statement` hands out before it stops with
+    // `TooManyStatements`, for callers that parse untrusted input.
+    pub max_statements: usize,
 }
 
 impl Parser {
@@ -11,41 +98,361 @@ impl Parser {
         Self {
             tokens: tokens
                 .iter()
-                .filter(|token| token.kind != TokenKind::Whitespace)
-                .map(|token| token.clone())
+                .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+                .cloned()
                 .collect(),
             current: 0,
+            errors: Vec::new(),
+            statement_bounds: Vec::new(),
+            equals_is_equality: false,
+            at_precedence: 4,
+            flat_precedence: false,
+            max_nesting: 64,
+            depth: 0,
+            max_statements: usize::MAX,
         }
     }
 
+    // Like `new`, but for tokens that did not come straight from the lexer:
+    // a stream containing a `Bad` token is rejected before parsing starts.
+    pub fn from_tokens(tokens: Vec<Token>) -> Result<Self, ParseError> {
+        if let Some(token) = tokens.iter().find(|token| token.kind == TokenKind::Bad) {
+            return Err(ParseError::BadToken(token.span.clone()));
+        }
+        Ok(Self::new(tokens))
+    }
+
+    // Keeps every token, trivia included, so `remaining_tokens` lines up with
+    // the lexer output one to one; whitespace and comments are stepped over
+    // as the parser advances instead.
+    pub fn with_trivia(tokens: Vec<Token>) -> Self {
+        let mut parser = Self {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            statement_bounds: Vec::new(),
+            equals_is_equality: false,
+            at_precedence: 4,
+            flat_precedence: false,
+            max_nesting: 64,
+            depth: 0,
+            max_statements: usize::MAX,
+        };
+        parser.skip_trivia();
+        parser
+    }
+
+    // Returns `None` once the input is exhausted or a statement fails to
+    // parse; in the latter case the error is available from `errors`.
     pub fn next_statement(&mut self) -> Option<ASTStatement> {
-        return self.parse_statement();
+        match self.parse_statement() {
+            // Statements kept by `reparse_from` still count towards the limit.
+            Ok(Some(_)) if self.statement_bounds.len() >= self.max_statements => {
+                self.errors.push(ParseError::TooManyStatements);
+                None
+            }
+            Ok(statement) => {
+                if statement.is_some() {
+                    self.record_statement_bounds();
+                }
+                statement
+            }
+            Err(error) => {
+                self.errors.push(error);
+                None
+            }
+        }
     }
 
-    fn parse_statement(&mut self) -> Option<ASTStatement> {
-        let token = self.current()?;
-        let expr = self.parse_expression()?;
-        return Some(ASTStatement::expression(expr));
+    // Picks up after an edit at `edit_offset` in the source, given the tokens
+    // of the edited source. Statements returned so far that end, together
+    // with the token that ended them, before the edit are kept; the rest must
+    // be parsed again through `next_statement`. Returns how many were kept,
+    // so the caller can truncate its `Ast` to that length and continue.
+    //
+    // Only a prefix is reused: statements after the edit have shifted spans,
+    // so they are parsed again too.
+    pub fn reparse_from(&mut self, edit_offset: usize, new_tokens: Vec<Token>) -> usize {
+        let kept = self
+            .statement_bounds
+            .iter()
+            .take_while(|(_, lookahead_end)| *lookahead_end < edit_offset)
+            .count();
+        self.statement_bounds.truncate(kept);
+        let resume = self.statement_bounds.last().map_or(0, |(end, _)| *end);
+        // Trivia is stepped over while parsing, so the tokens can be stored
+        // as given whichever constructor built this parser.
+        self.tokens = new_tokens;
+        self.current = self
+            .tokens
+            .iter()
+            .position(|token| token.span.start >= resume)
+            .unwrap_or(self.tokens.len());
+        self.skip_trivia();
+        self.errors.clear();
+        kept
     }
 
-    fn parse_expression(&mut self) -> Option<ASTExpression> {
-        return self.parse_binary_expression(0);
+    fn record_statement_bounds(&mut self) {
+        let end = self.previous_end();
+        let lookahead_end = self.current().map_or(usize::MAX, |token| token.span.end);
+        self.statement_bounds.push((end, lookahead_end));
     }
 
-    fn parse_binary_expression(&mut self, precedence: u8) -> Option<ASTExpression> {
-        let mut left = self.parse_primary_expression()?;
+    // For REPL continuation: true when `input` only fails to parse because it
+    // ended too early, e.g. an unclosed `(` or `{`, rather than because it is
+    // malformed.
+    pub fn is_incomplete(input: &str) -> bool {
+        let (tokens, lex_errors) = Lexer::tokenize(input);
+        if let Some(error) = lex_errors.first() {
+            return matches!(
+                error,
+                LexError::UnterminatedComment(_) | LexError::UnterminatedIdentifier(_)
+            );
+        }
+        let mut parser = Parser::new(tokens);
+        while parser.next_statement().is_some() {}
+        match parser.errors.first() {
+            Some(ParseError::UnexpectedEof) => true,
+            // `1 +` is waiting for its operand, but `1 + )` is malformed.
+            Some(ParseError::MissingRightOperand(operator)) => parser
+                .tokens
+                .iter()
+                .all(|token| token.span.start < operator.end),
+            _ => false,
+        }
+    }
 
-        while let Some(operator) = self.parse_binary_operator() {
+    // Parses exactly one expression, for embedding in another grammar.
+    // Unlike `next_statement`, anything after it, even `;`, is an error.
+    pub fn parse_single_expression(&mut self) -> Result<ASTExpression, ParseError> {
+        let expression = self.parse_expression()?;
+        match self.current() {
+            Some(token) if token.kind != TokenKind::Eof => {
+                Err(ParseError::TrailingTokens(token.span.clone()))
+            }
+            _ => Ok(expression),
+        }
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn remaining_tokens(&self) -> &[Token] {
+        &self.tokens[self.current.min(self.tokens.len())..]
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.current()
+            .is_none_or(|token| token.kind == TokenKind::Eof)
+    }
+
+    fn parse_statement(&mut self) -> Result<Option<ASTStatement>, ParseError> {
+        // Commas only reach this point at the top level; the ones between
+        // call arguments are consumed by parse_call_arguments. Of several
+        // doc comments in a row, the last one is kept.
+        let mut doc = None;
+        loop {
+            match self.current_kind() {
+                Some(TokenKind::Term) | Some(TokenKind::Comma) => {}
+                Some(TokenKind::DocComment(text)) => doc = Some(text.clone()),
+                _ => break,
+            }
             self.consume();
-            let operator_precedence = operator.precedence();
+        }
+        if self.is_at_end() {
+            return Ok(None);
+        }
+        let first = self.current;
+        let mut statement = match self.current_kind() {
+            Some(TokenKind::Define) => self.parse_function_declaration()?,
+            Some(TokenKind::Var) => self.parse_variable_declaration()?,
+            Some(TokenKind::Print) => self.parse_print()?,
+            // A bare `name = value` updates the nearest existing binding, so
+            // inside a block it changes an outer variable instead of
+            // shadowing it. With no binding to update it declares one.
+            Some(TokenKind::Variable(_))
+                if self.peek_significant(1).map(|token| &token.kind)
+                    == Some(&TokenKind::Assing) =>
+            {
+                self.parse_assignment()?
+            }
+            _ => ASTStatement::expression(self.parse_expression()?),
+        };
+        statement.doc = doc;
+        statement.span = self.span_from(first);
+        Ok(Some(statement))
+    }
+
+    // The span of the tokens from index `first` up to the last one consumed.
+    // The parser never sees the source itself, so the literal is rebuilt
+    // from the tokens, with any trivia that was dropped read as spaces.
+    fn span_from(&self, first: usize) -> TextSpan {
+        let start = self.tokens[first].span.start;
+        let end = self.previous_end();
+        let mut literal = String::new();
+        for token in &self.tokens[first..self.current] {
+            if token.span.end > end {
+                break;
+            }
+            // Tokens built by hand need not have increasing spans.
+            let gap = token
+                .span
+                .start
+                .saturating_sub(start)
+                .saturating_sub(literal.len());
+            literal.push_str(&" ".repeat(gap));
+            literal.push_str(&token.span.literal);
+        }
+        TextSpan::new(start, end, literal)
+    }
+
+    // def name(param, ...) = body
+    fn parse_function_declaration(&mut self) -> Result<ASTStatement, ParseError> {
+        self.advance()?;
+        let name = self.parse_identifier()?;
+        self.expect(TokenKind::LeftParen)?;
+        let mut parameters = Vec::new();
+        if self.current_kind() != Some(&TokenKind::RightParen) {
+            loop {
+                parameters.push(self.parse_variable_name()?);
+                if self.current_kind() != Some(&TokenKind::Comma) {
+                    break;
+                }
+                self.consume();
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::Assing)?;
+        let body = self.parse_expression()?;
+        Ok(ASTStatement::function_declaration(name, parameters, body))
+    }
+
+    // var name = initializer
+    fn parse_variable_declaration(&mut self) -> Result<ASTStatement, ParseError> {
+        self.advance()?;
+        let (name, initializer) = self.parse_binding()?;
+        Ok(ASTStatement::variable_declaration(
+            name.name().to_string(),
+            name.span().clone(),
+            initializer,
+        ))
+    }
+
+    // print expression, ...
+    fn parse_print(&mut self) -> Result<ASTStatement, ParseError> {
+        self.advance()?;
+        let mut arguments = vec![self.parse_expression()?];
+        while self.current_kind() == Some(&TokenKind::Comma) {
+            self.consume();
+            arguments.push(self.parse_expression()?);
+        }
+        Ok(ASTStatement::print(arguments))
+    }
+
+    fn parse_assignment(&mut self) -> Result<ASTStatement, ParseError> {
+        let (name, initializer) = self.parse_binding()?;
+        Ok(ASTStatement::assignment(
+            name.name().to_string(),
+            name.span().clone(),
+            initializer,
+        ))
+    }
+
+    // name = initializer, shared by declarations and assignments.
+    fn parse_binding(&mut self) -> Result<(ASTVariableExpression, ASTExpression), ParseError> {
+        let name = self.parse_variable_name()?;
+        self.expect(TokenKind::Assing)?;
+        let initializer = self.parse_expression()?;
+        Ok((name, initializer))
+    }
+
+    // An identifier in a declaration, keeping its span for diagnostics.
+    fn parse_variable_name(&mut self) -> Result<ASTVariableExpression, ParseError> {
+        let token = self.advance()?;
+        match &token.kind {
+            TokenKind::Variable(name) => {
+                Ok(ASTVariableExpression::new(name.clone(), token.span.clone()))
+            }
+            _ => Err(ParseError::UnexpectedToken(token.clone())),
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<String, ParseError> {
+        let token = self.advance()?;
+        match &token.kind {
+            TokenKind::Variable(name) => Ok(name.clone()),
+            _ => Err(ParseError::UnexpectedToken(token.clone())),
+        }
+    }
+
+    fn parse_call_arguments(&mut self) -> Result<Vec<ASTExpression>, ParseError> {
+        let mut arguments = Vec::new();
+        if self.current_kind() != Some(&TokenKind::RightParen) {
+            loop {
+                arguments.push(self.parse_expression()?);
+                if self.current_kind() != Some(&TokenKind::Comma) {
+                    break;
+                }
+                self.consume();
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+        Ok(arguments)
+    }
+
+    fn parse_expression(&mut self) -> Result<ASTExpression, ParseError> {
+        self.parse_binary_expression(0)
+    }
+
+    // Precedence climbing: only operators binding at least as tightly as
+    // `precedence` are consumed here, so looser ones are left for the caller.
+    fn parse_binary_expression(&mut self, precedence: u8) -> Result<ASTExpression, ParseError> {
+        let start = self.current_start();
+        let mut left = self.parse_postfix_expression()?;
+
+        while let Some(operator) = self.parse_binary_operator() {
+            let operator_precedence = match operator.kind {
+                _ if self.flat_precedence => 1,
+                ASTBinaryOperatorKind::At => self.at_precedence,
+                _ => operator.precedence(),
+            };
             if operator_precedence < precedence {
                 break;
             }
-            let right = self.parse_binary_expression(operator_precedence)?;
-            left = ASTExpression::binary(operator, left, right);
+            self.consume();
+            let right_precedence = if operator.is_right_associative() && !self.flat_precedence {
+                operator_precedence
+            } else {
+                operator_precedence + 1
+            };
+            if self.at_operand_end() {
+                return Err(ParseError::MissingRightOperand(operator.token.span.clone()));
+            }
+            // Right-associative chains like `2 ^ 2 ^ 2` recurse once per
+            // operator, so they count toward `max_nesting` as well.
+            let right = self.nested(|parser| parser.parse_binary_expression(right_precedence))?;
+            left =
+                ASTExpression::binary(operator, left, right).with_span(start, self.previous_end());
         }
 
-        return Some(left);
+        Ok(left)
+    }
+
+    // True when nothing that could start an operand follows, e.g. `2 +` at
+    // the end of the input or before `;`.
+    fn at_operand_end(&self) -> bool {
+        matches!(
+            self.current_kind(),
+            None | Some(TokenKind::Eof)
+                | Some(TokenKind::Term)
+                | Some(TokenKind::Comma)
+                | Some(TokenKind::RightParen)
+                | Some(TokenKind::End)
+                | Some(TokenKind::Then)
+                | Some(TokenKind::Else)
+        )
     }
 
     fn parse_binary_operator(&mut self) -> Option<ASTBinaryOperator> {
@@ -56,47 +463,222 @@ impl Parser {
             TokenKind::Asterisk => Some(ASTBinaryOperatorKind::Multiply),
             TokenKind::Slash => Some(ASTBinaryOperatorKind::Divide),
             TokenKind::Pow => Some(ASTBinaryOperatorKind::Pow),
+            TokenKind::Xor => Some(ASTBinaryOperatorKind::BitXor),
+            TokenKind::At => Some(ASTBinaryOperatorKind::At),
             TokenKind::IntegerDivide => Some(ASTBinaryOperatorKind::IntegerDivide),
+            TokenKind::Less => Some(ASTBinaryOperatorKind::Less),
+            TokenKind::LessEqual => Some(ASTBinaryOperatorKind::LessEqual),
+            TokenKind::Greater => Some(ASTBinaryOperatorKind::Greater),
+            TokenKind::GreaterEqual => Some(ASTBinaryOperatorKind::GreaterEqual),
+            TokenKind::EqualEqual => Some(ASTBinaryOperatorKind::Equal),
+            TokenKind::BangEqual => Some(ASTBinaryOperatorKind::NotEqual),
+            TokenKind::Assing if self.equals_is_equality => Some(ASTBinaryOperatorKind::Equal),
             _ => None,
         };
-        return kind.map(|kind| ASTBinaryOperator::new(kind, token.clone()));
+        kind.map(|kind| ASTBinaryOperator::new(kind, token.clone()))
     }
-    fn parse_primary_expression(&mut self) -> Option<ASTExpression> {
-        let token = self.consume()?;
-        return match &token.kind {
-            TokenKind::Real(number) => Some(ASTExpression::number(*number)),
-            TokenKind::Variable(name) => Some(ASTExpression::variable(name.clone())),
+    // Postfix `!` binds tighter than any binary operator, so `2^3!` is
+    // `2^(3!)`.
+    fn parse_postfix_expression(&mut self) -> Result<ASTExpression, ParseError> {
+        let start = self.current_start();
+        let mut expression = self.parse_primary_expression()?;
+        while self.current_kind() == Some(&TokenKind::Bang) {
+            self.consume();
+            expression = ASTExpression::factorial(expression).with_span(start, self.previous_end());
+        }
+        Ok(expression)
+    }
+
+    fn parse_primary_expression(&mut self) -> Result<ASTExpression, ParseError> {
+        let start = self.current_start();
+        let expression = self.nested(Self::parse_primary_kind)?;
+        Ok(expression.with_span(start, self.previous_end()))
+    }
+
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_nesting {
+            let span = self.current().map(|token| token.span.clone());
+            return Err(span.map_or(ParseError::UnexpectedEof, ParseError::TooDeep));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_primary_kind(&mut self) -> Result<ASTExpression, ParseError> {
+        let token = self.advance()?;
+        match &token.kind {
+            TokenKind::Real(number, unit) => {
+                let expression = ASTExpression::number_with_unit(*number, unit.clone());
+                if self.current_kind() != Some(&TokenKind::Percent) {
+                    return Ok(expression);
+                }
+                self.consume();
+                Ok(expression.into_percent())
+            }
+            TokenKind::Boolean(value) => Ok(ASTExpression::boolean(*value)),
+            TokenKind::Variable(name) => {
+                let name = name.clone();
+                let span = token.span.clone();
+                if self.current_kind() == Some(&TokenKind::LeftParen) {
+                    self.consume();
+                    let arguments = self.parse_call_arguments()?;
+                    return Ok(ASTExpression::call(name, arguments));
+                }
+                Ok(ASTExpression::variable(name, span))
+            }
             TokenKind::LeftParen => {
                 let expr = self.parse_expression()?;
-                let token = self.consume()?;
-                if token.kind != TokenKind::RightParen {
-                    panic!("Expected right paren");
-                }
-                Some(ASTExpression::parenthesized(expr))
+                self.expect(TokenKind::RightParen)?;
+                Ok(ASTExpression::parenthesized(expr))
             }
-            TokenKind::Begin => {
+            TokenKind::Begin => self.parse_block(),
+            // `|` is both the opening and closing bar: the inner expression
+            // stops at the next `|` since it isn't a binary operator, so each
+            // nested pair is balanced by the recursion.
+            TokenKind::Bar => {
                 let expr = self.parse_expression()?;
-                let token = self.consume()?;
-                if token.kind != TokenKind::End {
-                    panic!("Expected right paren");
-                }
-                Some(ASTExpression::start_end(expr))
+                self.expect(TokenKind::Bar)?;
+                Ok(ASTExpression::absolute(expr))
             }
-            _ => None,
-        };
+            // The else branch is a full expression, so `else if` chains
+            // nest to the right without extra handling.
+            TokenKind::If => {
+                let condition = self.parse_expression()?;
+                self.expect(TokenKind::Then)?;
+                let then_branch = self.parse_expression()?;
+                self.expect(TokenKind::Else)?;
+                let else_branch = self.parse_expression()?;
+                Ok(ASTExpression::conditional(
+                    condition,
+                    then_branch,
+                    else_branch,
+                ))
+            }
+            // Negation binds looser than `^` but tighter than everything
+            // else, so `-2^2` is `-(2^2)` and `-2*3` is `(-2)*3`.
+            TokenKind::Minus => {
+                let operand = self.parse_binary_expression(
+                    ASTBinaryOperator::synthetic(ASTBinaryOperatorKind::Pow).precedence(),
+                )?;
+                Ok(ASTExpression::unary(ASTUnaryOperatorKind::Neg, operand))
+            }
+            TokenKind::Eof => Err(ParseError::UnexpectedEof),
+            _ => Err(ParseError::UnexpectedToken(token.clone())),
+        }
+    }
+
+    // { statement; ... } with the opening brace already consumed. Statements
+    // are separated by `;`, and a trailing one before `}` is allowed.
+    fn parse_block(&mut self) -> Result<ASTExpression, ParseError> {
+        let mut statements = Vec::new();
+        loop {
+            while self.current_kind() == Some(&TokenKind::Term) {
+                self.consume();
+            }
+            if self.current_kind() == Some(&TokenKind::End) {
+                break;
+            }
+            let statement = self.parse_statement()?.ok_or(ParseError::UnexpectedEof)?;
+            statements.push(statement);
+            if self.current_kind() != Some(&TokenKind::Term) {
+                break;
+            }
+        }
+        self.expect(TokenKind::End)?;
+        Ok(ASTExpression::block(statements))
+    }
+
+    // Running into the end of the input is reported as `UnexpectedEof` so
+    // callers can tell unfinished input from malformed input.
+    fn expect(&mut self, kind: TokenKind) -> Result<&Token, ParseError> {
+        let token = self.advance()?;
+        if token.kind == kind {
+            return Ok(token);
+        }
+        match token.kind {
+            TokenKind::Eof => Err(ParseError::UnexpectedEof),
+            _ => Err(ParseError::Expected {
+                expected: kind,
+                found: token.clone(),
+            }),
+        }
+    }
+
+    // Where the last token consumed, trivia aside, ends in the source.
+    fn previous_end(&self) -> usize {
+        self.tokens[..self.current]
+            .iter()
+            .rev()
+            .find(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+            .map_or(0, |token| token.span.end)
+    }
+
+    // Where the next token starts, or the end of the input if there is none.
+    fn current_start(&self) -> usize {
+        self.current()
+            .map_or_else(|| self.previous_end(), |token| token.span.start)
     }
 
     fn peek(&self, offset: isize) -> Option<&Token> {
-        self.tokens.get((self.current as isize + offset) as usize)
+        let index = self.current.checked_add_signed(offset)?;
+        self.tokens.get(index)
+    }
+
+    // Like `peek`, but counts only tokens that are not trivia, for parsers
+    // built with `with_trivia`.
+    fn peek_significant(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .skip(self.current)
+            .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment))
+            .nth(offset)
     }
 
     fn current(&self) -> Option<&Token> {
         self.peek(0)
     }
 
+    fn current_kind(&self) -> Option<&TokenKind> {
+        self.current().map(|token| &token.kind)
+    }
+
     fn consume(&mut self) -> Option<&Token> {
+        let index = self.current;
         self.current += 1;
-        let token = self.peek(-1)?;
-        return Some(token);
+        self.skip_trivia();
+        self.tokens.get(index)
+    }
+
+    fn skip_trivia(&mut self) {
+        while matches!(
+            self.current_kind(),
+            Some(TokenKind::Whitespace) | Some(TokenKind::Comment)
+        ) {
+            self.current += 1;
+        }
+    }
+
+    // Like `consume`, but running out of tokens is an error.
+    fn advance(&mut self) -> Result<&Token, ParseError> {
+        self.consume().ok_or(ParseError::UnexpectedEof)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek_out_of_range_is_none() {
+        let (tokens, _) = Lexer::tokenize("1 + 2");
+        let parser = Parser::new(tokens);
+        assert!(parser.peek(-1).is_none());
+        assert!(parser.peek(100).is_none());
+        assert!(parser.peek(0).is_some());
     }
 }
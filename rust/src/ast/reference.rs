@@ -0,0 +1,124 @@
+use crate::ast::lexer::{Lexer, TokenKind};
+use crate::prelude::*;
+
+// A deliberately naive shunting-yard evaluator that shares nothing with the
+// parser except the lexer. It exists so tests can run the same input through
+// both and catch precedence or associativity regressions in the AST path.
+//
+// Only numbers, parentheses, unary minus and `+ - * / // ^` are understood;
+// anything else, and division by zero, gives `None`.
+pub fn reference_eval(input: &str) -> Option<f64> {
+    let (tokens, errors) = Lexer::tokenize(input);
+    if !errors.is_empty() {
+        return None;
+    }
+
+    let mut output: Vec<f64> = Vec::new();
+    let mut operators: Vec<Operator> = Vec::new();
+    // True when the next token should start an operand, which is what tells
+    // a unary `-` apart from a binary one.
+    let mut expect_operand = true;
+
+    for token in &tokens {
+        match &token.kind {
+            TokenKind::Real(number, None) if expect_operand => {
+                output.push(*number);
+                expect_operand = false;
+            }
+            TokenKind::LeftParen if expect_operand => operators.push(Operator::LeftParen),
+            TokenKind::RightParen if !expect_operand => loop {
+                match operators.pop()? {
+                    Operator::LeftParen => break,
+                    operator => operator.apply(&mut output)?,
+                }
+            },
+            TokenKind::Minus if expect_operand => operators.push(Operator::Negate),
+            kind if !expect_operand => {
+                let operator = Operator::binary(kind)?;
+                while let Some(top) = operators.last() {
+                    let pops = top.precedence() > operator.precedence()
+                        || (top.precedence() == operator.precedence() && operator != Operator::Pow);
+                    if *top == Operator::LeftParen || !pops {
+                        break;
+                    }
+                    operators.pop()?.apply(&mut output)?;
+                }
+                operators.push(operator);
+                expect_operand = true;
+            }
+            _ => return None,
+        }
+    }
+    if expect_operand {
+        return None;
+    }
+    while let Some(operator) = operators.pop() {
+        if operator == Operator::LeftParen {
+            return None;
+        }
+        operator.apply(&mut output)?;
+    }
+    match output.as_slice() {
+        [value] => Some(*value),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Operator {
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    IntegerDivide,
+    Negate,
+    Pow,
+    LeftParen,
+}
+
+impl Operator {
+    fn binary(kind: &TokenKind) -> Option<Self> {
+        match kind {
+            TokenKind::Plus => Some(Operator::Plus),
+            TokenKind::Minus => Some(Operator::Minus),
+            TokenKind::Asterisk => Some(Operator::Multiply),
+            TokenKind::Slash => Some(Operator::Divide),
+            TokenKind::IntegerDivide => Some(Operator::IntegerDivide),
+            TokenKind::Pow => Some(Operator::Pow),
+            _ => None,
+        }
+    }
+
+    // Negation sits between `*` and `^`, so `-2^2` is `-(2^2)`.
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::LeftParen => 0,
+            Operator::Plus | Operator::Minus => 1,
+            Operator::Multiply | Operator::Divide | Operator::IntegerDivide => 2,
+            Operator::Negate => 3,
+            Operator::Pow => 4,
+        }
+    }
+
+    fn apply(self, output: &mut Vec<f64>) -> Option<()> {
+        if self == Operator::Negate {
+            let operand = output.pop()?;
+            output.push(-operand);
+            return Some(());
+        }
+        let right = output.pop()?;
+        let left = output.pop()?;
+        let result = match self {
+            Operator::Plus => left + right,
+            Operator::Minus => left - right,
+            Operator::Multiply => left * right,
+            Operator::Divide | Operator::IntegerDivide if right == 0.0 => return None,
+            Operator::Divide => left / right,
+            Operator::IntegerDivide => (left / right).floor(),
+            Operator::Pow => left.powf(right),
+            Operator::Negate | Operator::LeftParen => return None,
+        };
+        output.push(result);
+        Some(())
+    }
+}
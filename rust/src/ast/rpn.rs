@@ -0,0 +1,149 @@
+use crate::ast::lexer::TextSpan;
+use crate::ast::{
+    ASTAbsoluteExpression, ASTBinaryExpression, ASTBinaryOperatorKind, ASTCallExpression,
+    ASTConditionalExpression, ASTEvaluator, ASTExpression, ASTFactorialExpression,
+    ASTNumberExpression, ASTStartEndExpression, ASTStatementKind, ASTUnaryExpression,
+    ASTUnaryOperatorKind, ASTVariableExpression, ASTVisitor, EvalError, Value,
+};
+use crate::prelude::*;
+
+// One entry of a program in Reverse Polish Notation. Operands come before
+// the operator that consumes them, so no parentheses are needed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpnToken {
+    Number(f64),
+    Variable(String),
+    Operator(ASTBinaryOperatorKind),
+    Negate,
+    Absolute,
+    Factorial,
+}
+
+// Post-order walk of `expression`. Conditionals, calls, percentages and
+// blocks of several statements have no flat postfix form, so an expression
+// containing one gives `None`.
+pub fn to_rpn(expression: &ASTExpression) -> Option<Vec<RpnToken>> {
+    let mut builder = RpnBuilder {
+        tokens: Vec::new(),
+        supported: true,
+    };
+    builder.visit_expression(expression);
+    builder.supported.then_some(builder.tokens)
+}
+
+// Runs `tokens` on an operand stack. Each operator is applied by the tree
+// evaluator's own steps, so both backends agree on every result and error.
+pub fn eval_rpn(tokens: &[RpnToken], vars: &HashMap<String, f64>) -> Result<f64, EvalError> {
+    let mut evaluator = ASTEvaluator::new();
+    for (name, value) in vars {
+        evaluator.define(name.clone(), *value);
+    }
+    let mut stack: Vec<Value> = Vec::new();
+    for token in tokens {
+        match token {
+            RpnToken::Number(number) => {
+                stack.push(Value::Number(*number));
+                continue;
+            }
+            RpnToken::Variable(name) => {
+                let span = TextSpan::new(0, name.len(), name.clone());
+                evaluator.visit_variable(&ASTVariableExpression::new(name.clone(), span));
+            }
+            RpnToken::Operator(operator) => {
+                let right = pop(&mut stack)?;
+                let left = pop(&mut stack)?.into_number()?;
+                evaluator.last_value = Some(right);
+                evaluator.finish_binary(operator, Some(left), None);
+            }
+            RpnToken::Negate => {
+                evaluator.last_value = Some(pop(&mut stack)?);
+                evaluator.finish_unary(&ASTUnaryOperatorKind::Neg);
+            }
+            RpnToken::Absolute => {
+                evaluator.last_value = Some(pop(&mut stack)?);
+                evaluator.finish_absolute();
+            }
+            RpnToken::Factorial => {
+                evaluator.last_value = Some(pop(&mut stack)?);
+                evaluator.finish_factorial();
+            }
+        }
+        if let Some(error) = evaluator.error.take() {
+            return Err(error);
+        }
+        stack.extend(evaluator.last_value.take());
+    }
+    match stack.pop() {
+        Some(value) if stack.is_empty() => value.into_number(),
+        _ => Err(EvalError::MalformedRpn),
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Result<Value, EvalError> {
+    stack.pop().ok_or(EvalError::MalformedRpn)
+}
+
+struct RpnBuilder {
+    tokens: Vec<RpnToken>,
+    supported: bool,
+}
+
+impl ASTVisitor for RpnBuilder {
+    fn visit_number(&mut self, number: &ASTNumberExpression) {
+        if number.is_percent() {
+            self.supported = false;
+        }
+        self.tokens.push(RpnToken::Number(number.number));
+    }
+
+    fn visit_boolean(&mut self, _value: bool) {
+        self.supported = false;
+    }
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        self.tokens.push(RpnToken::Variable(variable.name.clone()));
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+        self.tokens
+            .push(RpnToken::Operator(binary_expression.operator.kind.clone()));
+    }
+
+    fn visit_unary_expression(&mut self, unary_expression: &ASTUnaryExpression) {
+        self.visit_expression(&unary_expression.operand);
+        self.tokens.push(match unary_expression.operator {
+            ASTUnaryOperatorKind::Neg => RpnToken::Negate,
+        });
+    }
+
+    fn visit_absolute_expression(&mut self, absolute_expression: &ASTAbsoluteExpression) {
+        self.visit_expression(&absolute_expression.expression);
+        self.tokens.push(RpnToken::Absolute);
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+        self.tokens.push(RpnToken::Factorial);
+    }
+
+    // Only a block holding a single expression has a postfix form.
+    fn visit_start_end_expression(&mut self, start_end_expression: &ASTStartEndExpression) {
+        match start_end_expression.statements.as_slice() {
+            [statement] => match &statement.kind {
+                ASTStatementKind::Expression(expression) => self.visit_expression(expression),
+                _ => self.supported = false,
+            },
+            _ => self.supported = false,
+        }
+    }
+
+    fn visit_conditional_expression(&mut self, _conditional: &ASTConditionalExpression) {
+        self.supported = false;
+    }
+
+    fn visit_call(&mut self, _call: &ASTCallExpression) {
+        self.supported = false;
+    }
+}
@@ -0,0 +1,219 @@
+use crate::ast::{
+    ASTBinaryExpression, ASTBinaryOperatorKind, ASTCallExpression, ASTConditionalExpression,
+    ASTExpression, ASTExpressionKind, ASTFactorialExpression, ASTFunctionDeclaration,
+    ASTNumberExpression, ASTStatement, ASTVariableDeclaration, ASTVariableExpression, ASTVisitor,
+    Ast, EvalError,
+};
+use crate::prelude::*;
+use alloc::collections::BTreeMap;
+
+// A product of base units with integer exponents, so `m*m/s` is
+// `{m: 2, s: -1}`. The empty map is a plain number.
+type Unit = BTreeMap<String, i32>;
+
+// Checks that units written on literals (`3m`) are used consistently: `+`,
+// `-` and comparisons need the same unit on both sides, `*` and `/` combine
+// them, and `^` scales them by a literal integer exponent.
+//
+// The check is static. Parameters and variables it has not seen declared,
+// such as ones passed in by the caller, have an unknown unit that matches
+// anything, and so does the result of a call.
+#[derive(Default)]
+pub struct UnitChecker {
+    // `None` while the unit of the last expression is unknown.
+    last_unit: Option<Unit>,
+    variables: HashMap<String, Option<Unit>>,
+    error: Option<EvalError>,
+}
+
+impl UnitChecker {
+    pub fn check(ast: &Ast) -> Result<(), EvalError> {
+        // The check itself recurses, so programs without units skip it
+        // rather than limiting how deeply they can nest.
+        if !Self::uses_units(ast) {
+            return Ok(());
+        }
+        let mut checker = UnitChecker::default();
+        for statement in &ast.statements {
+            checker.visit_statement(statement);
+            if let Some(error) = checker.error {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn uses_units(ast: &Ast) -> bool {
+        let mut pending: Vec<&ASTExpression> = ast
+            .statements
+            .iter()
+            .flat_map(ASTStatement::expressions)
+            .collect();
+        while let Some(expression) = pending.pop() {
+            if let ASTExpressionKind::Number(number) = &expression.kind {
+                if number.unit.is_some() {
+                    return true;
+                }
+            }
+            pending.extend(expression.children());
+        }
+        false
+    }
+
+    fn mismatch(&mut self, left: &Unit, right: &Unit) {
+        if self.error.is_none() {
+            self.error = Some(EvalError::UnitMismatch {
+                left: Self::describe(left),
+                right: Self::describe(right),
+            });
+        }
+        self.last_unit = None;
+    }
+
+    fn describe(unit: &Unit) -> String {
+        if unit.is_empty() {
+            return "1".to_string();
+        }
+        unit.iter()
+            .map(|(name, exponent)| match exponent {
+                1 => name.clone(),
+                _ => format!("{}^{}", name, exponent),
+            })
+            .collect::<Vec<_>>()
+            .join("*")
+    }
+
+    // Adds `sign` times the exponents of `right` to `left`, dropping bases
+    // that cancel out.
+    fn combine(mut left: Unit, right: Unit, sign: i32) -> Unit {
+        for (name, exponent) in right {
+            let total = left.get(&name).copied().unwrap_or(0) + sign * exponent;
+            if total == 0 {
+                left.remove(&name);
+            } else {
+                left.insert(name, total);
+            }
+        }
+        left
+    }
+
+    // Both sides must agree when both are known; the result is whichever is
+    // known.
+    fn same(&mut self, left: Option<Unit>, right: Option<Unit>) -> Option<Unit> {
+        match (left, right) {
+            (Some(left), Some(right)) if left != right => {
+                self.mismatch(&left, &right);
+                None
+            }
+            (Some(unit), _) | (_, Some(unit)) => Some(unit),
+            (None, None) => None,
+        }
+    }
+}
+
+impl ASTVisitor for UnitChecker {
+    fn visit_variable_declaration(&mut self, declaration: &ASTVariableDeclaration) {
+        self.visit_expression(&declaration.initializer);
+        self.variables
+            .insert(declaration.name.clone(), self.last_unit.clone());
+    }
+
+    fn visit_function_declaration(&mut self, declaration: &ASTFunctionDeclaration) {
+        let outer = self.variables.clone();
+        for parameter in &declaration.parameters {
+            self.variables.insert(parameter.name.clone(), None);
+        }
+        self.visit_expression(&declaration.body);
+        self.variables = outer;
+    }
+
+    fn visit_number(&mut self, number: &ASTNumberExpression) {
+        let mut unit = Unit::new();
+        if let Some(name) = &number.unit {
+            unit.insert(name.clone(), 1);
+        }
+        self.last_unit = Some(unit);
+    }
+
+    fn visit_boolean(&mut self, _value: bool) {
+        self.last_unit = Some(Unit::new());
+    }
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        self.last_unit = self.variables.get(&variable.name).cloned().flatten();
+    }
+
+    fn visit_factorial_expression(&mut self, factorial_expression: &ASTFactorialExpression) {
+        self.visit_expression(&factorial_expression.expression);
+        let operand = self.last_unit.take();
+        self.last_unit = self.same(operand, Some(Unit::new()));
+    }
+
+    fn visit_conditional_expression(&mut self, conditional: &ASTConditionalExpression) {
+        self.visit_expression(&conditional.condition);
+        self.visit_expression(&conditional.then_branch);
+        let then_unit = self.last_unit.take();
+        self.visit_expression(&conditional.else_branch);
+        let else_unit = self.last_unit.take();
+        self.last_unit = self.same(then_unit, else_unit);
+    }
+
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        for argument in &call.arguments {
+            self.visit_expression(argument);
+        }
+        self.last_unit = None;
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        self.visit_expression(&binary_expression.left);
+        let left = self.last_unit.take();
+        self.visit_expression(&binary_expression.right);
+        let right = self.last_unit.take();
+
+        self.last_unit = match binary_expression.operator.kind {
+            ASTBinaryOperatorKind::Plus
+            | ASTBinaryOperatorKind::Minus
+            | ASTBinaryOperatorKind::BitXor => self.same(left, right),
+            ASTBinaryOperatorKind::Less
+            | ASTBinaryOperatorKind::LessEqual
+            | ASTBinaryOperatorKind::Greater
+            | ASTBinaryOperatorKind::GreaterEqual
+            | ASTBinaryOperatorKind::Equal
+            | ASTBinaryOperatorKind::NotEqual => {
+                self.same(left, right);
+                Some(Unit::new())
+            }
+            ASTBinaryOperatorKind::Multiply => match (left, right) {
+                (Some(left), Some(right)) => Some(Self::combine(left, right, 1)),
+                _ => None,
+            },
+            ASTBinaryOperatorKind::Divide | ASTBinaryOperatorKind::IntegerDivide => {
+                match (left, right) {
+                    (Some(left), Some(right)) => Some(Self::combine(left, right, -1)),
+                    _ => None,
+                }
+            }
+            // What a custom operator does to units is up to its handler.
+            ASTBinaryOperatorKind::At => None,
+            ASTBinaryOperatorKind::Pow => {
+                self.same(right, Some(Unit::new()));
+                match (left, &binary_expression.right.kind) {
+                    (Some(left), _) if left.is_empty() => Some(left),
+                    (Some(left), ASTExpressionKind::Number(exponent))
+                        if exponent.number.fract() == 0.0 =>
+                    {
+                        let exponent = exponent.number as i32;
+                        Some(
+                            left.into_iter()
+                                .map(|(name, power)| (name, power * exponent))
+                                .filter(|(_, power)| *power != 0)
+                                .collect(),
+                        )
+                    }
+                    _ => None,
+                }
+            }
+        };
+    }
+}
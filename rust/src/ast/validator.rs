@@ -0,0 +1,100 @@
+use crate::ast::lexer::{Token, TokenKind};
+use crate::ast::{
+    ASTBinaryExpression, ASTBinaryOperatorKind, ASTNumberExpression, ASTVariableExpression,
+    ASTVisitor,
+};
+use crate::prelude::*;
+use core::fmt;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ValidationError {
+    EmptyProgram,
+    MismatchedOperatorToken(Token),
+    NonFiniteNumber(f64),
+    EmptyVariableName,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptyProgram => write!(f, "empty program"),
+            ValidationError::MismatchedOperatorToken(token) => write!(
+                f,
+                "operator does not match its token `{}`",
+                token.span.literal
+            ),
+            ValidationError::NonFiniteNumber(number) => {
+                write!(f, "non-finite number `{}`", number)
+            }
+            ValidationError::EmptyVariableName => write!(f, "empty variable name"),
+        }
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+pub struct ASTValidator {
+    pub errors: Vec<ValidationError>,
+}
+
+impl Default for ASTValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ASTValidator {
+    pub fn new() -> Self {
+        Self { errors: Vec::new() }
+    }
+
+    fn operator_matches_token(kind: &ASTBinaryOperatorKind, token: &Token) -> bool {
+        matches!(
+            (kind, &token.kind),
+            (ASTBinaryOperatorKind::Plus, TokenKind::Plus)
+                | (ASTBinaryOperatorKind::Minus, TokenKind::Minus)
+                | (ASTBinaryOperatorKind::Multiply, TokenKind::Asterisk)
+                | (ASTBinaryOperatorKind::Divide, TokenKind::Slash)
+                | (ASTBinaryOperatorKind::Pow, TokenKind::Pow)
+                | (ASTBinaryOperatorKind::BitXor, TokenKind::Xor)
+                | (ASTBinaryOperatorKind::At, TokenKind::At)
+                | (
+                    ASTBinaryOperatorKind::IntegerDivide,
+                    TokenKind::IntegerDivide
+                )
+                | (ASTBinaryOperatorKind::Less, TokenKind::Less)
+                | (ASTBinaryOperatorKind::LessEqual, TokenKind::LessEqual)
+                | (ASTBinaryOperatorKind::Greater, TokenKind::Greater)
+                | (ASTBinaryOperatorKind::GreaterEqual, TokenKind::GreaterEqual)
+                | (ASTBinaryOperatorKind::Equal, TokenKind::EqualEqual)
+                | (ASTBinaryOperatorKind::Equal, TokenKind::Assing)
+                | (ASTBinaryOperatorKind::NotEqual, TokenKind::BangEqual)
+        )
+    }
+}
+
+impl ASTVisitor for ASTValidator {
+    fn visit_number(&mut self, number: &ASTNumberExpression) {
+        if !number.number.is_finite() {
+            self.errors
+                .push(ValidationError::NonFiniteNumber(number.number));
+        }
+    }
+
+    fn visit_variable(&mut self, variable: &ASTVariableExpression) {
+        if variable.name.is_empty() {
+            self.errors.push(ValidationError::EmptyVariableName);
+        }
+    }
+
+    fn visit_binary_expression(&mut self, binary_expression: &ASTBinaryExpression) {
+        let operator = &binary_expression.operator;
+        if !Self::operator_matches_token(&operator.kind, &operator.token) {
+            self.errors.push(ValidationError::MismatchedOperatorToken(
+                operator.token.clone(),
+            ));
+        }
+        self.visit_expression(&binary_expression.left);
+        self.visit_expression(&binary_expression.right);
+    }
+}
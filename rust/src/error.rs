@@ -0,0 +1,62 @@
+use crate::ast::lexer::LexError;
+use crate::ast::parser::ParseError;
+use crate::ast::validator::ValidationError;
+use crate::ast::EvalError;
+use core::error::Error;
+use core::fmt;
+
+// Any error the library reports, so callers going through several stages
+// can use `?` with a single error type.
+#[derive(Debug, PartialEq, Clone)]
+pub enum CalcError {
+    Lex(LexError),
+    Parse(ParseError),
+    Validation(ValidationError),
+    Eval(EvalError),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcError::Lex(error) => write!(f, "lex error: {}", error),
+            CalcError::Parse(error) => write!(f, "parse error: {}", error),
+            CalcError::Validation(error) => write!(f, "invalid program: {}", error),
+            CalcError::Eval(error) => write!(f, "evaluation error: {}", error),
+        }
+    }
+}
+
+impl Error for CalcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CalcError::Lex(error) => Some(error),
+            CalcError::Parse(error) => Some(error),
+            CalcError::Validation(error) => Some(error),
+            CalcError::Eval(error) => Some(error),
+        }
+    }
+}
+
+impl From<LexError> for CalcError {
+    fn from(error: LexError) -> Self {
+        CalcError::Lex(error)
+    }
+}
+
+impl From<ParseError> for CalcError {
+    fn from(error: ParseError) -> Self {
+        CalcError::Parse(error)
+    }
+}
+
+impl From<ValidationError> for CalcError {
+    fn from(error: ValidationError) -> Self {
+        CalcError::Validation(error)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(error: EvalError) -> Self {
+        CalcError::Eval(error)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::ast::Value;
+use crate::prelude::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Radix {
+    Binary,
+    Octal,
+    Decimal,
+    Hexadecimal,
+}
+
+impl Radix {
+    pub fn from_base(base: u32) -> Option<Self> {
+        match base {
+            2 => Some(Radix::Binary),
+            8 => Some(Radix::Octal),
+            10 => Some(Radix::Decimal),
+            16 => Some(Radix::Hexadecimal),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FormatWarning {
+    NotAnInteger { value: f64, radix: Radix },
+}
+
+pub struct ResultFormatter {
+    pub radix: Radix,
+    // Decimal places shown for results printed in decimal. Only the text is
+    // rounded; the value itself is untouched.
+    pub round_to: Option<usize>,
+    // Split the integer part of decimal results into groups of three with
+    // `group_separator`, so `1000000.5` prints as `1,000,000.5`.
+    pub group_digits: bool,
+    pub group_separator: char,
+}
+
+impl Default for ResultFormatter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResultFormatter {
+    pub fn new() -> Self {
+        Self {
+            radix: Radix::Decimal,
+            round_to: None,
+            group_digits: false,
+            group_separator: ',',
+        }
+    }
+
+    pub fn with_radix(radix: Radix) -> Self {
+        Self {
+            radix,
+            round_to: None,
+            group_digits: false,
+            group_separator: ',',
+        }
+    }
+
+    // Only integer-valued results can be shown in a non-decimal radix; anything
+    // else is printed in decimal and reported back as a warning.
+    pub fn format(&self, value: f64) -> (String, Option<FormatWarning>) {
+        if self.radix == Radix::Decimal {
+            return (self.decimal(value), None);
+        }
+        let Some(integer) = Self::as_integer(value) else {
+            let warning = FormatWarning::NotAnInteger {
+                value,
+                radix: self.radix,
+            };
+            return (self.decimal(value), Some(warning));
+        };
+        let sign = if integer < 0 { "-" } else { "" };
+        let magnitude = integer.unsigned_abs();
+        let digits = match self.radix {
+            Radix::Binary => format!("0b{:b}", magnitude),
+            Radix::Octal => format!("0o{:o}", magnitude),
+            Radix::Hexadecimal => format!("0x{:X}", magnitude),
+            Radix::Decimal => format!("{}", magnitude),
+        };
+        (format!("{}{}", sign, digits), None)
+    }
+
+    // Booleans print as `true`/`false` regardless of the radix.
+    pub fn format_value(&self, value: &Value) -> (String, Option<FormatWarning>) {
+        match value {
+            Value::Number(number) => self.format(*number),
+            _ => (value.to_string(), None),
+        }
+    }
+
+    fn decimal(&self, value: f64) -> String {
+        let text = match self.round_to {
+            Some(places) => format!("{:.*}", places, value),
+            None => format!("{}", value),
+        };
+        if self.group_digits {
+            self.group(&text)
+        } else {
+            text
+        }
+    }
+
+    // Text such as `inf` or `NaN` has no digits to group and is returned as
+    // is.
+    fn group(&self, text: &str) -> String {
+        let (sign, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", text),
+        };
+        let (integer, fraction) = match unsigned.find('.') {
+            Some(dot) => unsigned.split_at(dot),
+            None => (unsigned, ""),
+        };
+        if !integer.bytes().all(|byte| byte.is_ascii_digit()) {
+            return text.to_string();
+        }
+        let mut grouped = String::from(sign);
+        for (index, digit) in integer.chars().enumerate() {
+            if index > 0 && (integer.len() - index) % 3 == 0 {
+                grouped.push(self.group_separator);
+            }
+            grouped.push(digit);
+        }
+        grouped.push_str(fraction);
+        grouped
+    }
+
+    fn as_integer(value: f64) -> Option<i64> {
+        if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+            Some(value as i64)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,85 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod ast;
+pub mod error;
+pub mod format;
+
+// What the std prelude would provide, for modules that must also build
+// without it.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+
+    // std's maps when available, so they are the same types callers pass
+    // in; hashbrown's otherwise.
+    #[cfg(not(feature = "std"))]
+    pub use hashbrown::{HashMap, HashSet};
+    #[cfg(feature = "std")]
+    pub use std::collections::{HashMap, HashSet};
+
+    #[cfg(not(feature = "std"))]
+    pub use crate::float::Float;
+}
+
+// The float methods `core` lacks, from libm. With std the inherent methods
+// take precedence and this is not compiled.
+#[cfg(not(feature = "std"))]
+mod float {
+    pub trait Float {
+        fn floor(self) -> Self;
+        fn fract(self) -> Self;
+        fn powf(self, exponent: Self) -> Self;
+    }
+
+    impl Float for f64 {
+        fn floor(self) -> Self {
+            libm::floor(self)
+        }
+        fn fract(self) -> Self {
+            self - libm::trunc(self)
+        }
+        fn powf(self, exponent: Self) -> Self {
+            libm::pow(self, exponent)
+        }
+    }
+
+    impl Float for f32 {
+        fn floor(self) -> Self {
+            libm::floorf(self)
+        }
+        fn fract(self) -> Self {
+            self - libm::truncf(self)
+        }
+        fn powf(self, exponent: Self) -> Self {
+            libm::powf(self, exponent)
+        }
+    }
+}
+
+use crate::ast::lexer::Lexer;
+use crate::ast::parser::Parser;
+use crate::ast::Ast;
+use crate::error::CalcError;
+
+// Runs `input` from source to result, stopping at the first error of any
+// stage. Evaluation uses the iterative evaluator, so no input, however
+// deeply nested, should panic or overflow the stack.
+pub fn try_eval(input: &str) -> Result<f64, CalcError> {
+    let (tokens, lex_errors) = Lexer::tokenize(input);
+    if let Some(error) = lex_errors.into_iter().next() {
+        return Err(error.into());
+    }
+    let mut ast = Ast::new();
+    let mut parser = Parser::new(tokens);
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    if let Some(error) = parser.errors().first() {
+        return Err(error.clone().into());
+    }
+    Ok(ast.evaluate_iterative()?)
+}
@@ -1,30 +1,82 @@
-use crate::ast::lexer::Lexer;
-use crate::ast::parser::Parser;
-use crate::ast::Ast; // Ensure this path is correct
+use rust::ast::lexer::Lexer;
+use rust::ast::parser::Parser;
+use rust::ast::{ASTEvaluator, ASTStatementKind, Ast, Value};
+use rust::format::{FormatWarning, Radix, ResultFormatter};
+
+enum TreeOutput {
+    Text,
+    Xml,
+}
 
-mod ast;
-// com
 fn main() {
-    let input = "2 + 3 * (2 + 4)";
-    let mut lexer = Lexer::new(input);
-    let mut tokens = Vec::new();
-    while let Some(token) = lexer.next_token() {
-        tokens.push(token);
+    let mut formatter = ResultFormatter::new();
+    let mut tree_only = None;
+    let mut input = String::from("var a = 2; var b = 3; a * b");
+    for arg in std::env::args().skip(1) {
+        if arg == "--ast" {
+            tree_only = Some(TreeOutput::Text);
+        } else if arg == "--xml" {
+            tree_only = Some(TreeOutput::Xml);
+        } else if let Some(base) = arg.strip_prefix("--radix=") {
+            match base.parse().ok().and_then(Radix::from_base) {
+                Some(radix) => formatter.radix = radix,
+                None => eprintln!("Unsupported radix: {}", base),
+            }
+        } else if let Some(places) = arg.strip_prefix("--round=") {
+            match places.parse() {
+                Ok(places) => formatter.round_to = Some(places),
+                Err(_) => eprintln!("Invalid number of decimal places: {}", places),
+            }
+        } else if !arg.starts_with("--") {
+            input = arg;
+        }
+    }
+
+    let (tokens, lex_errors) = Lexer::tokenize(&input);
+    for error in lex_errors {
+        eprintln!("Lex error: {:?}", error);
     }
     let mut ast: Ast = Ast::new();
     let mut parser = Parser::new(tokens);
-    loop {
-        match parser.next_statement() {
-            Some(stmt) => ast.add_statement(stmt),
-            None => break,
-        }
+    while let Some(stmt) = parser.next_statement() {
+        ast.add_statement(stmt);
+    }
+    for error in parser.errors() {
+        eprintln!("Parse error: {:?}", error);
+    }
+    match tree_only {
+        Some(TreeOutput::Text) => return ast.visualize(),
+        Some(TreeOutput::Xml) => return ast.visualizeXML(),
+        None => ast.visualize(),
     }
-    ast.visualize();
     // Evaluate the AST
-    let result = ast.evaluate();
-    if let Some(result) = result {
-        println!("Result: {}", result);
-    } else {
-        println!("No result");
+    let mut evaluator = ASTEvaluator::new();
+    let result = evaluator.evaluate_value(&ast);
+    for value in &evaluator.output {
+        println!("{}", format_result(&formatter, value));
+    }
+    // Only a trailing bare expression reports its value; a script that ends
+    // with a declaration or a `print` has already said what it wants to.
+    let ends_with_expression = matches!(
+        ast.statements.last().map(|statement| &statement.kind),
+        Some(ASTStatementKind::Expression(_))
+    );
+    match result {
+        Ok(result) if ends_with_expression => {
+            println!("Result: {}", format_result(&formatter, &result))
+        }
+        Ok(_) => {}
+        Err(_) => println!("No result"),
+    }
+}
+
+fn format_result(formatter: &ResultFormatter, value: &Value) -> String {
+    let (text, warning) = formatter.format_value(value);
+    if let Some(FormatWarning::NotAnInteger { radix, .. }) = warning {
+        eprintln!(
+            "Warning: result is not an integer, {:?} output falls back to decimal",
+            radix
+        );
     }
+    text
 }
@@ -0,0 +1,346 @@
+use rust::ast::lexer::{Lexer, TextSpan, Token, TokenKind};
+use rust::ast::parser::Parser;
+use rust::ast::validator::ValidationError;
+use rust::ast::{
+    ASTBinaryOperator, ASTBinaryOperatorKind, ASTCallExpression, ASTExpression, ASTExpressionKind,
+    ASTMutVisitor, ASTNumberExpression, ASTStatement, ASTStatementKind, ASTVariableExpression,
+    ASTVisitor, Ast,
+};
+
+fn parse(input: &str) -> Ast {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    ast
+}
+
+fn variable(name: &str) -> ASTExpression {
+    ASTExpression::variable(name.to_string(), TextSpan::new(0, 0, name.to_string()))
+}
+
+fn program(expression: ASTExpression) -> Ast {
+    let mut ast = Ast::new();
+    ast.add_statement(ASTStatement::expression(expression));
+    ast
+}
+
+#[test]
+fn builder_constructs_evaluable_trees() {
+    let ast = program(ASTExpression::mul(
+        ASTExpression::add(ASTExpression::number(1.0), ASTExpression::number(2.0)),
+        ASTExpression::pow(variable("x"), ASTExpression::number(2.0)),
+    ));
+    assert_eq!(ast.to_source(), "(1 + 2) * x ^ 2");
+    assert_eq!(ast.evaluate_with([("x".to_string(), 3.0)].into()), Ok(27.0));
+
+    let ast = program(ASTExpression::integer_divide(
+        ASTExpression::sub(ASTExpression::number(9.0), ASTExpression::number(2.0)),
+        ASTExpression::div(ASTExpression::number(4.0), ASTExpression::number(2.0)),
+    ));
+    assert_eq!(ast.to_source(), "(9 - 2) // (4 / 2)");
+    assert_eq!(ast.evaluate(), parse("(9 - 2) // (4 / 2)").evaluate());
+}
+
+#[test]
+fn validate_accepts_parsed_programs() {
+    assert_eq!(parse("1 + 2 * x; var y = 3; y").validate(), Ok(()));
+}
+
+#[test]
+fn validate_reports_malformed_trees() {
+    assert_eq!(
+        parse("").validate(),
+        Err(vec![ValidationError::EmptyProgram])
+    );
+
+    let minus = Token::new(TokenKind::Minus, TextSpan::new(0, 1, "-".to_string()));
+    let ast = program(ASTExpression::binary(
+        ASTBinaryOperator::new(ASTBinaryOperatorKind::Plus, minus.clone()),
+        ASTExpression::number(f64::INFINITY),
+        variable(""),
+    ));
+    assert_eq!(
+        ast.validate(),
+        Err(vec![
+            ValidationError::MismatchedOperatorToken(minus),
+            ValidationError::NonFiniteNumber(f64::INFINITY),
+            ValidationError::EmptyVariableName,
+        ])
+    );
+}
+
+struct CallCounter(usize);
+
+impl ASTVisitor for CallCounter {
+    fn visit_number(&mut self, _: &ASTNumberExpression) {}
+    fn visit_variable(&mut self, _: &ASTVariableExpression) {}
+    fn visit_call(&mut self, call: &ASTCallExpression) {
+        self.0 += 1;
+        for argument in call.arguments() {
+            self.visit_expression(argument);
+        }
+    }
+}
+
+#[test]
+fn visit_call_sees_nested_calls() {
+    let mut counter = CallCounter(0);
+    parse("f(g(1), 2) + h()").visit(&mut counter);
+    assert_eq!(counter.0, 3);
+}
+
+#[test]
+fn literal_division_by_zero_is_detected() {
+    assert!(parse("1 / 0").has_literal_division_by_zero());
+    assert!(parse("3 + 1 // 0").has_literal_division_by_zero());
+    assert!(!parse("1 / (2 - 2)").has_literal_division_by_zero());
+    assert!(!parse("1 / 2").has_literal_division_by_zero());
+}
+
+#[test]
+fn operation_count_includes_call_arguments() {
+    assert_eq!(parse("2 + 3 * 4").operation_count(), 2);
+    assert_eq!(parse("f(1 + 2, 3)").operation_count(), 2);
+}
+
+#[test]
+fn rename_variable_leaves_parameters_alone() {
+    let mut ast = parse("x + x * 2");
+    ast.rename_variable("x", "t");
+    assert_eq!(ast.to_source(), "t + t * 2");
+
+    let mut ast = parse("def f(x) = x + y; var x = 1; f(x)");
+    ast.rename_variable("x", "t");
+    assert_eq!(
+        ast.visualize_string(),
+        parse("def f(x) = x + y; var t = 1; f(t)").visualize_string()
+    );
+}
+
+struct Doubler;
+
+impl ASTMutVisitor for Doubler {
+    fn visit_number_mut(&mut self, number: &mut ASTNumberExpression) {
+        *number.number_mut() *= 2.0;
+    }
+}
+
+#[test]
+fn mut_visitor_rewrites_numbers_in_place() {
+    let mut ast = parse("1 + f(2) * |3|");
+    ast.visit_mut(&mut Doubler);
+    assert_eq!(
+        ast.visualize_string(),
+        parse("2 + f(4) * |6|").visualize_string()
+    );
+}
+
+#[test]
+fn max_paren_depth() {
+    assert_eq!(parse("1 + 2").max_paren_depth(), 0);
+    assert_eq!(parse("((1))").max_paren_depth(), 2);
+    assert_eq!(parse("(1) + {(2)}; ((((x))))").max_paren_depth(), 4);
+}
+
+#[test]
+fn approx_eq_compares_constants_within_a_tolerance() {
+    let sum = parse("0.1 + 0.2");
+    assert!(sum.approx_eq(&parse("0.1 + 0.20000000000000004"), 1e-12));
+    assert!(parse("0.30000000000000004").approx_eq(&parse("0.3"), 1e-12));
+    assert!(!parse("0.30000000000000004").approx_eq(&parse("0.3"), 0.0));
+
+    assert!(!sum.approx_eq(&parse("0.1 + 0.3"), 1e-6));
+    assert!(!sum.approx_eq(&parse("0.1 - 0.2"), 1e-6));
+    assert!(!sum.approx_eq(&parse("(0.1 + 0.2)"), 1e-6));
+}
+
+#[test]
+fn parameters_shadowing_variables_are_reported() {
+    assert_eq!(
+        parse("var x = 1; def f(x) = x; f(2)").shadowed_variables(),
+        vec![("x".to_string(), TextSpan::new(17, 18, "x".to_string()))]
+    );
+    assert!(parse("var x = 1; var x = 2; x = 3")
+        .shadowed_variables()
+        .is_empty());
+    assert!(parse("def f(x) = x; var x = 1")
+        .shadowed_variables()
+        .is_empty());
+}
+
+#[test]
+fn operators_used() {
+    use std::collections::HashSet;
+
+    assert_eq!(
+        parse("2 ^ 3 + 1").operators_used(),
+        HashSet::from([ASTBinaryOperatorKind::Pow, ASTBinaryOperatorKind::Plus])
+    );
+    assert_eq!(
+        parse("def f(x) = x // 2; f(1) < 3").operators_used(),
+        HashSet::from([
+            ASTBinaryOperatorKind::IntegerDivide,
+            ASTBinaryOperatorKind::Less,
+        ])
+    );
+    assert!(parse("-1").operators_used().is_empty());
+}
+
+#[test]
+fn statement_count_and_empty_programs() {
+    let empty = parse("");
+    assert_eq!(empty.len(), 0);
+    assert!(empty.is_empty());
+
+    let two = parse("var a = 2; a * 3");
+    assert_eq!(two.len(), 2);
+    assert!(!two.is_empty());
+}
+
+#[test]
+fn node_at_finds_the_innermost_expression() {
+    let ast = parse("12 + 345");
+    let node = ast.node_at(5).unwrap();
+    assert_eq!(node.span(), Some((5, 8)));
+    assert!(matches!(node.kind(), ASTExpressionKind::Number(number) if number.number() == 345.0));
+    assert_eq!(ast.node_at(0).unwrap().span(), Some((0, 2)));
+    assert_eq!(ast.node_at(3).unwrap().span(), Some((0, 8)));
+    assert!(ast.node_at(8).is_none());
+
+    let ast = parse("-(1 + 2)! * {3; x}");
+    assert_eq!(ast.node_at(0).unwrap().span(), Some((0, 9)));
+    assert_eq!(ast.node_at(7).unwrap().span(), Some((1, 8)));
+    assert_eq!(ast.node_at(16).unwrap().span(), Some((16, 17)));
+}
+
+#[test]
+fn explain_precedence() {
+    assert_eq!(
+        parse("2 + 3 * 4").explain_precedence(),
+        "`*` (prec 4) binds before `+` (prec 3)"
+    );
+    assert_eq!(
+        parse("(2 + 3) * 4 - 1").explain_precedence(),
+        "parentheses make `+` (prec 3) bind before `*` (prec 4)\n\
+         `*` (prec 4) binds before `-` (prec 3)"
+    );
+    assert_eq!(
+        parse("2 ^ 3 ^ 2").explain_precedence(),
+        "`^` (prec 5) binds before `^` (prec 5), grouping right to left"
+    );
+    assert_eq!(parse("2").explain_precedence(), "");
+}
+
+#[test]
+fn canonicalize_orders_commutative_operands() {
+    let canonical = |input: &str| {
+        let mut ast = parse(input);
+        ast.canonicalize();
+        ast
+    };
+    assert!(canonical("3 + x").approx_eq(&canonical("x + 3"), 0.0));
+    assert_eq!(canonical("x + 3").to_source(), "3 + x");
+    assert_eq!(canonical("y * x").to_source(), "x * y");
+    assert_eq!(canonical("b * a * 2").to_source(), "2 * (a * b)");
+    assert_eq!(canonical("f(1) + x").to_source(), "x + f(1)");
+    assert_eq!(canonical("(2 + 1) * 5").to_source(), "5 * (1 + 2)");
+    assert_eq!(canonical("x - 3").to_source(), "x - 3");
+
+    // A percent literal is relative to the left operand of `+`.
+    assert_eq!(canonical("200 + 5%").to_source(), "200 + 5%");
+    assert_eq!(canonical("200 + 5%").evaluate(), Ok(210.0));
+    assert_eq!(canonical("5% + 200").evaluate(), Ok(200.05));
+}
+
+#[test]
+fn binary_operator_exposes_kind_span_and_precedence() {
+    let ast = parse("2 * 3");
+    let ASTExpressionKind::Binary(binary) = ast.node_at(2).unwrap().kind() else {
+        panic!("expected a binary expression");
+    };
+    let operator = binary.operator();
+    assert_eq!(operator.kind(), &ASTBinaryOperatorKind::Multiply);
+    assert_eq!(operator.span(), &TextSpan::new(2, 3, "*".to_string()));
+    assert_eq!(operator.span().length(), 1);
+    assert_eq!(operator.precedence(), 4);
+}
+
+#[test]
+fn expressions_hash_by_structure() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let hash = |input: &str| {
+        let ast = parse(input);
+        let ASTStatementKind::Expression(expression) = &ast.statements[0].kind else {
+            panic!("expected an expression statement");
+        };
+        let mut hasher = DefaultHasher::new();
+        expression.hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash("x + 1"), hash("  x  +   1"));
+    assert_ne!(hash("x + 1"), hash("x - 1"));
+}
+
+#[test]
+fn cse_hoists_repeated_subexpressions() {
+    let ast = parse("(x + 1) * (x + 1)").cse();
+    assert_eq!(ast.to_source(), "var tmp0 = x + 1; tmp0 * tmp0");
+    assert_eq!(ast.evaluate_with([("x".to_string(), 2.0)].into()), Ok(9.0));
+
+    let ast = parse("((x + 1) * (x + 1)) + ((x + 1) * (x + 1)) + (x + 1)").cse();
+    assert_eq!(
+        ast.to_source(),
+        "var tmp0 = x + 1; var tmp1 = tmp0 * tmp0; tmp1 + tmp1 + tmp0"
+    );
+    assert_eq!(ast.evaluate_with([("x".to_string(), 2.0)].into()), Ok(21.0));
+
+    assert_eq!(
+        parse("var tmp0 = 1; (tmp0 + 1) ^ (tmp0 + 1)")
+            .cse()
+            .to_source(),
+        "var tmp0 = 1; var tmp1 = tmp0 + 1; tmp1 ^ tmp1"
+    );
+    assert_eq!(
+        parse("(x > 0) + (x > 0) == if x > 0 then 1 else 2")
+            .cse()
+            .to_source(),
+        "var tmp0 = x > 0; tmp0 + tmp0 == (if tmp0 then 1 else 2)"
+    );
+    assert_eq!(
+        parse("print x * 2, x * 2").cse().to_source(),
+        "var tmp0 = x * 2; print tmp0, tmp0"
+    );
+}
+
+#[test]
+fn cse_leaves_unsafe_repeats_alone() {
+    for input in [
+        "x + 1",
+        "rand() + 1 + (rand() + 1)",
+        "if x > 0 then (1 / x) + (1 / x) else 0",
+        "(ans + 1) * (ans + 1)",
+        "def f(x) = (x + 1) * (x + 1)",
+        "{ x = 2; x + 1 } * (x + 1)",
+        "(x + 1) * (x + 1) + rand()",
+    ] {
+        assert_eq!(
+            parse(input).cse().to_source(),
+            parse(input).to_source(),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn cse_does_not_share_across_assignments() {
+    let input = "var x = 1; (x + 1) + { x = 5; 0 } + (x + 1)";
+    assert_eq!(parse(input).evaluate(), Ok(8.0));
+    assert_eq!(parse(input).cse().evaluate(), Ok(8.0));
+    assert_eq!(parse(input).cse().to_source(), parse(input).to_source());
+}
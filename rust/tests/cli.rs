@@ -0,0 +1,33 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust"))
+        .args(args)
+        .output()
+        .expect("failed to run binary");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn xml_flag_prints_tree_without_result() {
+    let stdout = run(&["--xml"]);
+    assert!(stdout.contains("<program>"));
+    assert!(!stdout.contains("Result:"));
+}
+
+#[test]
+fn default_program_evaluates_every_statement() {
+    assert!(run(&[]).contains("Result: 6"));
+}
+
+#[test]
+fn only_a_trailing_expression_prints_its_value() {
+    assert!(!run(&["var a = 5"]).contains("Result:"));
+    assert!(run(&["var a = 5; a + 1"]).contains("Result: 6"));
+}
+
+#[test]
+fn round_flag_limits_decimal_places() {
+    assert!(run(&["--round=2"]).contains("Result: 6.00"));
+}
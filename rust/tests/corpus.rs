@@ -0,0 +1,72 @@
+use rust::ast::lexer::{Lexer, TextSpan, Token, TokenKind};
+use rust::ast::parser::ParseError;
+use rust::ast::EvalError;
+use rust::error::CalcError;
+use std::fs;
+use std::panic;
+use std::path::Path;
+
+// Every file under `tests/corpus/` is an input that once made the library
+// panic. Whether each one evaluates or fails does not matter, only that it
+// returns. Add new finds as files; the name should say what was wrong.
+#[test]
+fn corpus_inputs_do_not_panic() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut panicked = Vec::new();
+    for entry in fs::read_dir(&directory).expect("failed to read corpus") {
+        let path = entry.expect("failed to read corpus entry").path();
+        let input = fs::read_to_string(&path).expect("corpus files must be UTF-8");
+        if panic::catch_unwind(|| rust::try_eval(&input)).is_err() {
+            panicked.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        }
+    }
+    assert!(panicked.is_empty(), "panicked on {:?}", panicked);
+}
+
+#[test]
+fn long_power_chain_is_too_deep() {
+    let input = ["2"; 20_000].join("^");
+    assert!(matches!(
+        rust::try_eval(&input),
+        Err(CalcError::Parse(ParseError::TooDeep(_)))
+    ));
+    assert_eq!(rust::try_eval("2 ^ 3 ^ 2"), Ok(512.0));
+}
+
+#[test]
+fn nesting_limit() {
+    let deep = format!("{}1{}", "(".repeat(65), ")".repeat(65));
+    assert!(matches!(
+        rust::try_eval(&deep),
+        Err(CalcError::Parse(ParseError::TooDeep(_)))
+    ));
+    let ok = format!("{}1{}", "(".repeat(60), ")".repeat(60));
+    assert_eq!(rust::try_eval(&ok), Ok(1.0));
+}
+
+#[test]
+fn non_ascii_input() {
+    assert_eq!(rust::try_eval("1\u{a0}+\u{a0}2"), Ok(3.0));
+    assert!(matches!(
+        rust::try_eval("π"),
+        Err(CalcError::Eval(EvalError::UndefinedVariable { .. }))
+    ));
+    assert!(matches!(rust::try_eval("€"), Err(CalcError::Lex(_))));
+
+    // Spans are byte offsets.
+    let (tokens, _) = Lexer::tokenize("π + é1");
+    assert_eq!(
+        tokens[0],
+        Token::new(
+            TokenKind::Variable("π".to_string()),
+            TextSpan::new(0, 2, "π".to_string())
+        )
+    );
+    assert_eq!(
+        tokens[2],
+        Token::new(
+            TokenKind::Variable("é1".to_string()),
+            TextSpan::new(5, 8, "é1".to_string())
+        )
+    );
+}
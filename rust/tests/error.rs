@@ -0,0 +1,59 @@
+use rust::ast::lexer::Lexer;
+use rust::ast::parser::Parser;
+use rust::ast::{Ast, EvalError};
+use rust::error::CalcError;
+use std::error::Error;
+
+fn calculate(input: &str) -> Result<f64, CalcError> {
+    let (tokens, lex_errors) = Lexer::tokenize(input);
+    if let Some(error) = lex_errors.into_iter().next() {
+        return Err(error.into());
+    }
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    if let Some(error) = parser.errors().first() {
+        return Err(error.clone().into());
+    }
+    Ok(ast.evaluate()?)
+}
+
+#[test]
+fn each_stage_converts_into_calc_error() {
+    assert_eq!(calculate("2 * 3"), Ok(6.0));
+    assert!(matches!(calculate("1 $"), Err(CalcError::Lex(_))));
+    assert!(matches!(calculate("2 *"), Err(CalcError::Parse(_))));
+    assert_eq!(
+        calculate("1 / 0"),
+        Err(CalcError::Eval(EvalError::DivisionByZero))
+    );
+}
+
+#[test]
+fn calc_error_is_a_std_error() {
+    let error: Box<dyn Error> = Box::new(calculate("2 *").unwrap_err());
+    assert_eq!(
+        error.to_string(),
+        "parse error: `*` at 2 is missing its right operand"
+    );
+    assert_eq!(
+        error.source().map(|source| source.to_string()),
+        Some("`*` at 2 is missing its right operand".to_string())
+    );
+}
+
+#[test]
+fn stage_errors_display_their_position() {
+    let (_, lex_errors) = Lexer::tokenize("1 $ 2");
+    assert_eq!(lex_errors[0].to_string(), "unexpected character `$` at 2");
+    assert_eq!(
+        calculate("(1 + 2 3")
+            .unwrap_err()
+            .source()
+            .unwrap()
+            .to_string(),
+        "expected RightParen but found `3` at 7"
+    );
+}
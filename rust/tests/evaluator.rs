@@ -0,0 +1,869 @@
+use rust::ast::integer::{IntEvaluator, IntOverflow};
+use rust::ast::lexer::{Lexer, TextSpan};
+use rust::ast::parser::Parser;
+use rust::ast::reference::reference_eval;
+use rust::ast::{
+    ASTBinaryOperatorKind, ASTEvaluator, ASTExpression, ASTStatement, Ast, EvalError, TraceStep,
+    Value,
+};
+
+fn parse(input: &str) -> Ast {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    ast
+}
+
+fn parse_units(input: &str) -> Ast {
+    let mut lexer = Lexer::new(input);
+    lexer.units = true;
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert!(parser.errors().is_empty(), "input: {}", input);
+    ast
+}
+
+fn evaluate(input: &str) -> f64 {
+    ASTEvaluator::new()
+        .evaluate(&parse(input))
+        .unwrap_or_else(|error| panic!("input: {}: {:?}", input, error))
+}
+
+#[test]
+fn bare_assignment_binds_a_variable() {
+    assert_eq!(evaluate("a = 2; a * 3"), 6.0);
+    assert_eq!(evaluate("var a = 1; a = a + 1; a"), 2.0);
+}
+
+#[test]
+fn evaluate_with_state_returns_final_bindings() {
+    let (result, variables) = parse("a = 1; b = 2").evaluate_with_state();
+    assert_eq!(result, Ok(2.0));
+    assert_eq!(variables["a"], Value::Number(1.0));
+    assert_eq!(variables["b"], Value::Number(2.0));
+}
+
+#[test]
+fn assignment_in_a_block_updates_the_outer_binding() {
+    assert_eq!(evaluate("var x = 1; { x = 2 }; x"), 2.0);
+    assert_eq!(evaluate("var x = 1; { { x = x + 1 }; x = x * 3 }; x"), 6.0);
+    assert_eq!(evaluate("var x = 1; { var x = 5; x = 6 }; x"), 1.0);
+    // With nothing to update, the assignment declares a block-local.
+    assert!(ASTEvaluator::new()
+        .evaluate(&parse("{ y = 2 }; y"))
+        .is_err());
+
+    let ast = parse("var x = 1; { x = 2 }; x");
+    assert_eq!(
+        ASTEvaluator::new().evaluate_iterative(&ast),
+        Ok(Value::Number(2.0))
+    );
+    assert_eq!(IntEvaluator::default().evaluate(&ast), Ok(2));
+    let ast = parse("var x = 1; { var x = 5; x = 6 }; x");
+    assert_eq!(IntEvaluator::default().evaluate(&ast), Ok(1));
+}
+
+#[test]
+fn assignment_is_not_reported_as_shadowing() {
+    assert!(parse("var x = 1; { x = 2 }")
+        .shadowed_variables()
+        .is_empty());
+    assert_eq!(
+        parse("var x = 1; { var x = 2 }").shadowed_variables().len(),
+        1
+    );
+}
+
+#[test]
+fn negative_and_fractional_exponents() {
+    assert_eq!(evaluate("2 ^ -2"), 0.25);
+    assert_eq!(evaluate("4 ^ 0.5"), 2.0);
+    assert_eq!(evaluate("2 ^ 0.5"), 2f64.sqrt());
+    assert_eq!(evaluate("8 ^ (1 / 3)"), 2.0);
+
+    let mut evaluator = ASTEvaluator::<f32>::default();
+    assert_eq!(evaluator.evaluate(&parse("2 ^ -1")), Ok(0.5f32));
+    assert_eq!(evaluator.evaluate(&parse("9 ^ 0.5")), Ok(3.0f32));
+}
+
+#[test]
+fn evaluate_samples_reuses_one_program() {
+    assert_eq!(
+        parse("x * x").evaluate_samples("x", &[0.0, 1.0, 2.0, 3.0]),
+        vec![Ok(0.0), Ok(1.0), Ok(4.0), Ok(9.0)]
+    );
+    assert_eq!(
+        parse("1 / x").evaluate_samples("x", &[0.0, 2.0]),
+        vec![Err(EvalError::DivisionByZero), Ok(0.5)]
+    );
+    assert!(matches!(
+        &parse("y + 1").evaluate_samples("x", &[1.0])[0],
+        Err(EvalError::UndefinedVariable { name, .. }) if name == "y"
+    ));
+}
+
+#[test]
+fn ans_holds_the_previous_value() {
+    assert_eq!(evaluate("2 + 3; ans * 2"), 10.0);
+    assert_eq!(evaluate("4; var x = ans + 1; x"), 5.0);
+    assert!(matches!(
+        parse("ans * 2").evaluate(),
+        Err(EvalError::UndefinedVariable { .. })
+    ));
+}
+
+#[test]
+fn every_binary_operator_evaluates() {
+    for (input, expected) in [
+        ("7 + 2", 9.0),
+        ("7 - 2", 5.0),
+        ("7 * 2", 14.0),
+        ("7 / 2", 3.5),
+        ("7 ^ 2", 49.0),
+        ("8 // 2", 4.0),
+    ] {
+        assert_eq!(evaluate(input), expected, "input: {}", input);
+    }
+}
+
+#[test]
+fn absolute_value_bars() {
+    assert_eq!(evaluate("|3 - 7|"), 4.0);
+    assert_eq!(evaluate("1 + 2 * |1 - 4|"), 7.0);
+    assert_eq!(evaluate("||1 - 5| - 10|"), 6.0);
+    assert!(parse("|1 - 5").evaluate().is_err());
+}
+
+#[test]
+fn strict_integer_divide_rejects_fractional_operands() {
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.strict_integer_divide = true;
+    assert_eq!(evaluator.evaluate(&parse("6 // 2")), Ok(3.0));
+    assert_eq!(
+        evaluator.evaluate(&parse("5.5 // 2")),
+        Err(EvalError::NonIntegerOperand)
+    );
+
+    assert_eq!(parse("5.5 // 2").evaluate(), Ok(2.0));
+    assert_eq!(parse("7 // 2").evaluate(), Ok(3.0));
+}
+
+#[test]
+fn checked_overflow_reports_infinite_results() {
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.checked_overflow = true;
+    evaluator.define("big".to_string(), 1e308);
+    assert_eq!(
+        evaluator.evaluate(&parse("big * 10")),
+        Err(EvalError::Overflow)
+    );
+    assert_eq!(evaluator.evaluate(&parse("big / 10")), Ok(1e307));
+
+    assert_eq!(
+        parse("big * 10").evaluate_with([("big".to_string(), 1e308)].into()),
+        Ok(f64::INFINITY)
+    );
+}
+
+#[test]
+fn user_defined_functions() {
+    assert_eq!(evaluate("def f(x) = x * x; f(5)"), 25.0);
+    assert_eq!(evaluate("def g(a, b) = a - b; g(10, 4)"), 6.0);
+    assert_eq!(evaluate("def k() = 7; k() + 1"), 8.0);
+
+    assert_eq!(
+        parse("def f(x) = f(x); f(1)").evaluate(),
+        Err(EvalError::RecursionLimit)
+    );
+    assert_eq!(
+        parse("def f(x) = x; f(1, 2)").evaluate(),
+        Err(EvalError::ArityMismatch {
+            name: "f".to_string(),
+            expected: 1,
+            found: 2,
+        })
+    );
+    assert_eq!(
+        parse("h(1)").evaluate(),
+        Err(EvalError::UndefinedFunction("h".to_string()))
+    );
+}
+
+#[test]
+fn pi_and_e_are_constants() {
+    assert!((evaluate("pi") - std::f64::consts::PI).abs() < 1e-12);
+    assert_eq!(evaluate("var pi = 1; pi + e"), 1.0 + std::f64::consts::E);
+}
+
+#[test]
+fn evaluate_traced_records_each_operation() {
+    let (result, trace) = parse("2 + 3 * 4").evaluate_traced();
+    assert_eq!(result, Ok(14.0));
+    assert_eq!(
+        trace,
+        vec![
+            TraceStep {
+                operator: ASTBinaryOperatorKind::Multiply,
+                left: 3.0,
+                right: 4.0,
+                result: Value::Number(12.0),
+            },
+            TraceStep {
+                operator: ASTBinaryOperatorKind::Plus,
+                left: 2.0,
+                right: 12.0,
+                result: Value::Number(14.0),
+            },
+        ]
+    );
+}
+
+#[test]
+fn scopes_shadow_and_restore_bindings() {
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.define("x".to_string(), 1.0);
+    evaluator.push_scope();
+    evaluator.define("x".to_string(), 2.0);
+    assert_eq!(evaluator.lookup("x"), Some(&Value::Number(2.0)));
+    evaluator.pop_scope();
+    assert_eq!(evaluator.lookup("x"), Some(&Value::Number(1.0)));
+    // The global scope is never popped.
+    evaluator.pop_scope();
+    assert_eq!(evaluator.lookup("x"), Some(&Value::Number(1.0)));
+
+    let ast = parse("var y = 5; def f(x) = x + y; f(10)");
+    assert_eq!(evaluator.evaluate(&ast), Ok(15.0));
+}
+
+#[test]
+fn top_level_commas_separate_results() {
+    assert_eq!(
+        parse("1 + 1, 2 + 2, 3 + 3").evaluate_all(),
+        Ok(vec![2.0, 4.0, 6.0])
+    );
+    assert_eq!(
+        parse("def f(a, b) = a + b, f(1, 2), 5").evaluate_all(),
+        Ok(vec![3.0, 5.0])
+    );
+}
+
+#[test]
+fn errors_propagate_as_values() {
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.trace = Some(Vec::new());
+    assert_eq!(
+        evaluator.evaluate_values(&parse("(1 / 0) + (2 * 3); 4 + 1")),
+        vec![Value::Error(EvalError::DivisionByZero), Value::Number(5.0)]
+    );
+    // The right operand is still evaluated after the left one failed.
+    let trace = evaluator.trace.unwrap();
+    assert_eq!(trace.len(), 2);
+    assert_eq!(trace[0].result, Value::Number(6.0));
+
+    assert_eq!(
+        parse("(1 / 0) + (2 * 3)").evaluate(),
+        Err(EvalError::DivisionByZero)
+    );
+}
+
+#[test]
+fn comparisons_evaluate_to_booleans() {
+    assert_eq!(parse("3 > 2").evaluate_value(), Ok(Value::Bool(true)));
+    assert_eq!(parse("3 > 2 + 1").evaluate_value(), Ok(Value::Bool(false)));
+    assert_eq!(
+        parse("(3 > 2) + 1").evaluate_value(),
+        Err(EvalError::TypeMismatch)
+    );
+    assert_eq!(
+        parse("1 <= 1 == 1").evaluate_value(),
+        Err(EvalError::TypeMismatch)
+    );
+    assert_eq!(parse("3 > 2").evaluate(), Err(EvalError::TypeMismatch));
+}
+
+#[test]
+fn factorial() {
+    let largest = evaluate("170!");
+    assert!(largest.is_finite() && largest > 7.25e306);
+    assert_eq!(parse("171!").evaluate(), Err(EvalError::Overflow));
+    assert_eq!(parse("2.5!").evaluate(), Err(EvalError::NonIntegerOperand));
+
+    assert_eq!(evaluate("2 ^ 3!"), 64.0);
+    assert_eq!(evaluate("3!!"), 720.0);
+    assert_eq!(
+        parse("0! + 2 != 3").evaluate_value(),
+        Ok(Value::Bool(false))
+    );
+}
+
+#[test]
+fn if_then_else() {
+    assert_eq!(evaluate("if 1 then 10 else 20 == 10"), 10.0);
+    assert_eq!(
+        evaluate("var x = 5; if x < 0 then 1 else if x == 5 then 2 else 3"),
+        2.0
+    );
+    // Only the chosen branch is evaluated.
+    assert_eq!(evaluate("if 0 then 1 / 0 else 7"), 7.0);
+    assert!(parse("If 1 then 2 else 3").evaluate().is_err());
+}
+
+#[test]
+fn caret_can_mean_xor() {
+    let mut lexer = Lexer::new("5 ^ 3 == 6; 2 ** 3");
+    lexer.caret_is_xor = true;
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert_eq!(
+        ASTEvaluator::new().evaluate_values(&ast),
+        vec![Value::Bool(true), Value::Number(8.0)]
+    );
+
+    assert_eq!(evaluate("2 ^ 3"), 8.0);
+}
+
+#[test]
+fn undefined_variable_carries_its_span() {
+    assert_eq!(
+        parse("1 + foo").evaluate(),
+        Err(EvalError::UndefinedVariable {
+            name: "foo".to_string(),
+            span: TextSpan::new(4, 7, "foo".to_string()),
+        })
+    );
+}
+
+#[test]
+fn on_operation_sees_every_binary_operation() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&seen);
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.on_operation = Some(Box::new(move |operator, left, right, result| {
+        sink.borrow_mut()
+            .push((operator.clone(), left, right, result.clone()));
+    }));
+    assert_eq!(evaluator.evaluate(&parse("2 + 3 * 4")), Ok(14.0));
+    assert_eq!(
+        *seen.borrow(),
+        vec![
+            (
+                ASTBinaryOperatorKind::Multiply,
+                3.0,
+                4.0,
+                Value::Number(12.0)
+            ),
+            (ASTBinaryOperatorKind::Plus, 2.0, 12.0, Value::Number(14.0)),
+        ]
+    );
+}
+
+#[test]
+fn integer_overflow_policies() {
+    let ast = parse("x + 1");
+    let run = |policy| {
+        let mut evaluator = IntEvaluator::new(policy);
+        evaluator.variables.insert("x".to_string(), i64::MAX);
+        evaluator.evaluate(&ast)
+    };
+    assert_eq!(run(IntOverflow::Checked), Err(EvalError::Overflow));
+    assert_eq!(run(IntOverflow::Wrapping), Ok(i64::MIN));
+    assert_eq!(run(IntOverflow::Saturating), Ok(i64::MAX));
+
+    assert_eq!(
+        parse("2 ^ 62 * 2").evaluate_int(IntOverflow::Saturating),
+        Ok(i64::MAX)
+    );
+    assert_eq!(
+        parse("21!").evaluate_int(IntOverflow::Checked),
+        Err(EvalError::Overflow)
+    );
+    assert_eq!(parse("20! > 0").evaluate_int(IntOverflow::Checked), Ok(1));
+    assert_eq!(
+        parse("3000000000!").evaluate_int(IntOverflow::Saturating),
+        Ok(i64::MAX)
+    );
+    assert_eq!(
+        parse("3000000000!").evaluate_int(IntOverflow::Wrapping),
+        Ok(0)
+    );
+    assert_eq!(
+        parse("3000000000!").evaluate_int(IntOverflow::Checked),
+        Err(EvalError::Overflow)
+    );
+    assert_eq!(
+        parse("(0 - 7) / 2").evaluate_int(IntOverflow::Checked),
+        Ok(-4)
+    );
+    assert_eq!(
+        parse("1.5").evaluate_int(IntOverflow::Checked),
+        Err(EvalError::NonIntegerOperand)
+    );
+}
+
+#[test]
+fn reference_interpreter_agrees_with_the_evaluator() {
+    for input in [
+        "1 + 2 * 3",
+        "(1 + 2) * 3",
+        "2 ^ 3 ^ 2",
+        "10 - 4 - 3",
+        "100 / 10 / 5",
+        "7 // 2 * 3",
+        "-2 ^ 2",
+        "2 * -3 + 4",
+        "-(1 - 4) ^ 2 // 2",
+        "((2))",
+        "1 / 0",
+        "1 +",
+        "(1",
+    ] {
+        assert_eq!(
+            reference_eval(input),
+            parse(input).evaluate().ok(),
+            "input: {}",
+            input
+        );
+    }
+    assert_eq!(reference_eval("x + 1"), None);
+}
+
+#[test]
+fn units_must_agree() {
+    assert_eq!(
+        parse_units("3m + 2m == 5m").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+    assert_eq!(
+        parse_units("3m + 2s").evaluate_value(),
+        Err(EvalError::UnitMismatch {
+            left: "m".to_string(),
+            right: "s".to_string(),
+        })
+    );
+    assert_eq!(
+        parse_units("var v = 6m / 2s; v - 1m").evaluate_value(),
+        Err(EvalError::UnitMismatch {
+            left: "m*s^-1".to_string(),
+            right: "m".to_string(),
+        })
+    );
+    assert!(parse_units("3m + 2").evaluate().is_err());
+    let mismatch = EvalError::UnitMismatch {
+        left: "m".to_string(),
+        right: "s".to_string(),
+    };
+    assert_eq!(
+        ASTEvaluator::new().evaluate_values(&parse_units("1m; 3m + 2s")),
+        vec![Value::Error(mismatch.clone()), Value::Error(mismatch)]
+    );
+
+    assert_eq!(parse_units("3m * 2s").evaluate(), Ok(6.0));
+    assert_eq!(
+        parse_units("var a = 2m * 3m; a + 1m ^ 2").evaluate(),
+        Ok(7.0)
+    );
+    assert_eq!(parse_units("def f(x) = x + 1m; f(2s)").evaluate(), Ok(3.0));
+    assert!(!parse_units("3m").approx_eq(&parse_units("3s"), 1.0));
+}
+
+#[test]
+fn empty_programs_are_an_error() {
+    assert_eq!(parse("").evaluate(), Err(EvalError::EmptyProgram));
+    assert_eq!(parse(";;").evaluate(), Err(EvalError::EmptyProgram));
+}
+
+#[test]
+fn at_operator_calls_the_registered_function() {
+    let ast = parse("2 @ 4 * 2");
+    let mut evaluator = ASTEvaluator::new();
+    assert_eq!(
+        evaluator.evaluate(&ast),
+        Err(EvalError::UnregisteredOperator("@".to_string()))
+    );
+    evaluator.register_operator("@", |a, b| (a + b) / 2.0);
+    // `@` binds like `*` by default: (2 @ 4) * 2.
+    assert_eq!(evaluator.evaluate(&ast), Ok(6.0));
+
+    let (tokens, _) = Lexer::tokenize("2 @ 4 * 2");
+    let mut parser = Parser::new(tokens);
+    parser.at_precedence = 3;
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert_eq!(evaluator.evaluate(&ast), Ok(5.0));
+}
+
+#[test]
+fn iterative_evaluator_handles_deep_trees() {
+    let mut expression = ASTExpression::number(1.0);
+    for _ in 1..50_000 {
+        expression = ASTExpression::add(expression, ASTExpression::number(1.0));
+    }
+    let mut ast = Ast::new();
+    ast.add_statement(ASTStatement::expression(expression));
+    assert_eq!(ast.evaluate_iterative(), Ok(50_000.0));
+}
+
+#[test]
+fn iterative_evaluator_matches_the_recursive_one() {
+    for input in [
+        "1 + 2 * 3 - 4 / 2",
+        "2 ^ 3 ^ 2",
+        "var a = 3; def f(x, y) = x * y + a; f(2, f(1, 1)) + ans",
+        "def fact(n) = if n < 2 then 1 else n * fact(n - 1); fact(10)",
+        "-|2 - 5|! + (1) + {2}",
+        "1 / 0",
+        "def f(x) = f(x); f(1)",
+        "undefined + 1",
+        "f(1)",
+        "3 > 2",
+        "if 3 > 2 then 7 else 1 / 0",
+        "(1 > 0) + 1",
+        "pi * 2",
+    ] {
+        let ast = parse(input);
+        assert_eq!(
+            ASTEvaluator::new().evaluate_iterative(&ast),
+            ASTEvaluator::new().evaluate_value(&ast),
+            "input: {}",
+            input
+        );
+    }
+
+    let ast = parse("1 / 0 + (2 * 3); 4 + 5");
+    let mut recursive = ASTEvaluator::new();
+    recursive.propagate_errors = true;
+    recursive.trace = Some(Vec::new());
+    let mut iterative = ASTEvaluator::new();
+    iterative.propagate_errors = true;
+    iterative.trace = Some(Vec::new());
+    assert_eq!(
+        iterative.evaluate_iterative(&ast),
+        recursive.evaluate_value(&ast)
+    );
+    assert_eq!(iterative.trace, recursive.trace);
+}
+
+#[test]
+fn error_source_names_the_failing_expression() {
+    let ast = parse("var x = 3; 4 + 1 / (2 - 2)");
+    let mut evaluator = ASTEvaluator::new();
+    assert_eq!(
+        evaluator.evaluate_value(&ast),
+        Err(EvalError::DivisionByZero)
+    );
+
+    evaluator.error_source = true;
+    let error = evaluator.evaluate_value(&ast).unwrap_err();
+    assert_eq!(
+        error,
+        EvalError::InExpression {
+            error: Box::new(EvalError::DivisionByZero),
+            source: "1 / (2 - 2)".to_string(),
+        }
+    );
+    assert_eq!(error.to_string(), "division by zero in `1 / (2 - 2)`");
+    assert_eq!(
+        Value::<f64>::Error(error.clone()).to_string(),
+        "error: division by zero in `1 / (2 - 2)`"
+    );
+    assert_eq!(evaluator.evaluate_iterative(&ast), Err(error));
+
+    let ast = parse("def f(a) = a + y; 2 * f(1)");
+    let error = evaluator.evaluate_value(&ast).unwrap_err();
+    assert_eq!(error.to_string(), "undefined variable `y` in `y`");
+    assert_eq!(evaluator.evaluate_iterative(&ast), Err(error));
+}
+
+#[test]
+fn evaluator_is_generic_over_the_number_type() {
+    let mut evaluator = ASTEvaluator::<f32>::default();
+    assert_eq!(evaluator.evaluate(&parse("0.1 + 0.2")), Ok(0.3f32));
+    // Literals f32 cannot hold exactly round to the nearest f32.
+    assert_eq!(evaluator.evaluate(&parse("0.1")), Ok(0.1f32));
+    assert_eq!(evaluator.evaluate(&parse("16777217")), Ok(16777216.0f32));
+    assert_eq!(evaluator.evaluate(&parse("|-4| + 3!")), Ok(10.0f32));
+    assert_eq!(
+        evaluator.evaluate(&parse("1 / 0")),
+        Err(EvalError::DivisionByZero)
+    );
+    evaluator.register_operator("@", |a: f32, b: f32| a.max(b));
+    assert_eq!(evaluator.evaluate(&parse("2 @ 5")), Ok(5.0f32));
+
+    for input in ["1 + 2 * 3", "7 // 2", "-(3 - 5) * 0.1", "5 ^ 3 == 125"] {
+        let ast = parse(input);
+        assert_eq!(
+            ASTEvaluator::<f64>::default().evaluate_iterative(&ast),
+            ast.evaluate_value(),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn evaluate_with_history_snapshots_each_statement() {
+    let history = parse("a = 1; b = a + 1").evaluate_with_history().unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["a"], 1.0);
+    assert!(!history[0].contains_key("b"));
+    assert_eq!(history[1]["a"], 1.0);
+    assert_eq!(history[1]["b"], 2.0);
+
+    assert!(matches!(
+        parse("a = 1; b = c").evaluate_with_history(),
+        Err(EvalError::UndefinedVariable { .. })
+    ));
+    // Only numeric bindings are recorded.
+    assert_eq!(
+        parse("x = 1 < 2; y = 3").evaluate_with_history().unwrap()[1].get("x"),
+        None
+    );
+}
+
+#[test]
+fn print_collects_output() {
+    let ast = parse("print 1, 2, 3");
+    let mut evaluator = ASTEvaluator::new();
+    assert_eq!(evaluator.evaluate(&ast), Ok(3.0));
+    assert_eq!(
+        evaluator.output,
+        vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)]
+    );
+    let mut iterative = ASTEvaluator::new();
+    iterative.evaluate_iterative(&ast).unwrap();
+    assert_eq!(iterative.output, evaluator.output);
+
+    // Output printed before an error is kept.
+    let mut evaluator = ASTEvaluator::new();
+    let ast = parse("a = 2; print a * 2, 1 < 2; print a; print q, 5");
+    assert!(evaluator.evaluate(&ast).is_err());
+    assert_eq!(
+        evaluator.output,
+        vec![Value::Number(4.0), Value::Bool(true), Value::Number(2.0)]
+    );
+}
+
+#[test]
+fn blocks_evaluate_to_their_last_statement() {
+    assert_eq!(evaluate("{ a = 1; a + 2 }"), 3.0);
+    assert_eq!(evaluate("{ a = 1; a + 2; } * 2"), 6.0);
+    assert_eq!(evaluate("{ x = 2; { y = x * 3; y + 1 } }"), 7.0);
+    assert_eq!(evaluate("7; { 1; 2 }; ans"), 2.0);
+    assert_eq!(evaluate("a = 5; { var a = 1; a } + a"), 6.0);
+    assert!(matches!(
+        parse("{ a = 1; a }; a").evaluate(),
+        Err(EvalError::UndefinedVariable { .. })
+    ));
+
+    assert_eq!(parse("{}").evaluate(), Err(EvalError::EmptyBlock));
+    assert_eq!(parse("{}").evaluate_iterative(), Err(EvalError::EmptyBlock));
+    assert_eq!(
+        parse("1 + { def f(x) = x }").evaluate(),
+        Err(EvalError::EmptyBlock)
+    );
+
+    let mut evaluator = ASTEvaluator::new();
+    evaluator.evaluate(&parse("{ print 1, 2; 3 }")).unwrap();
+    assert_eq!(
+        evaluator.output,
+        vec![Value::Number(1.0), Value::Number(2.0)]
+    );
+}
+
+#[test]
+fn integer_negative_exponents() {
+    let evaluate_int = |input: &str| parse(input).evaluate_int(IntOverflow::Checked);
+    assert_eq!(evaluate_int("2 ^ 3"), Ok(8));
+    assert_eq!(evaluate_int("1 ^ -5"), Ok(1));
+    assert_eq!(evaluate_int("(0 - 1) ^ -3"), Ok(-1));
+    assert_eq!(evaluate_int("2 ^ -1"), Err(EvalError::NonIntegerResult));
+    assert_eq!(evaluate_int("0 ^ -2"), Err(EvalError::DivisionByZero));
+}
+
+#[test]
+fn evaluate_borrow_leaves_the_map_alone() {
+    let mut variables = std::collections::HashMap::new();
+    variables.insert("x".to_string(), 2.0);
+    variables.insert("pi".to_string(), 3.0);
+    let before = variables.clone();
+    assert_eq!(parse("x * 10 + pi").evaluate_borrow(&variables), Ok(23.0));
+    assert_eq!(
+        parse("var x = 5; x + 1").evaluate_borrow(&variables),
+        Ok(6.0)
+    );
+    assert_eq!(
+        parse("{var x = 7; x} + x").evaluate_borrow(&variables),
+        Ok(9.0)
+    );
+    assert!(parse("y").evaluate_borrow(&variables).is_err());
+    assert_eq!(variables, before);
+}
+
+#[test]
+fn special_value_builtins() {
+    assert!(evaluate("nan()").is_nan());
+    assert_eq!(evaluate("inf()"), f64::INFINITY);
+    assert_eq!(evaluate("-inf()"), f64::NEG_INFINITY);
+    assert_eq!(evaluate("eps()"), f64::EPSILON);
+    assert_eq!(
+        parse("1 + eps() > 1").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+    assert_eq!(parse("inf()").evaluate_iterative(), Ok(f64::INFINITY));
+    assert!(matches!(
+        parse("eps(1)").evaluate(),
+        Err(EvalError::ArityMismatch { .. })
+    ));
+    // User definitions take precedence over the builtins.
+    assert_eq!(evaluate("def inf() = 2; inf()"), 2.0);
+
+    let strict = |input: &str| {
+        let mut evaluator = ASTEvaluator::new();
+        evaluator.strict_finite = true;
+        evaluator.evaluate(&parse(input))
+    };
+    assert_eq!(strict("nan()"), Err(EvalError::NonFinite));
+    assert_eq!(strict("1 / inf()"), Err(EvalError::NonFinite));
+    assert_eq!(strict("10 ^ 400"), Err(EvalError::NonFinite));
+    assert_eq!(strict("eps() * 2"), Ok(2.0 * f64::EPSILON));
+}
+
+#[test]
+fn percent_changes() {
+    assert_eq!(evaluate("200 + 5%"), 210.0);
+    assert_eq!(evaluate("200 - 10%"), 180.0);
+    assert_eq!(parse("200 - 10%").evaluate_iterative(), Ok(180.0));
+    assert_eq!(evaluate("200 * 5%"), 10.0);
+    assert_eq!(evaluate("50%"), 0.5);
+    assert_eq!(evaluate("x = 80; x + 25%"), 100.0);
+    assert_eq!(
+        parse("200 + 5% == 210").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+    assert!(parse("200 + 5%")
+        .evaluate_int(IntOverflow::Checked)
+        .is_err());
+}
+
+#[test]
+fn rand_is_reproducible_with_a_seed() {
+    let program = parse("print rand(), rand(), rand(10, 20)");
+    let run = |seed: u64| {
+        let mut evaluator = ASTEvaluator::with_seed(seed);
+        evaluator.evaluate(&program).unwrap();
+        evaluator.output
+    };
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+
+    let mut evaluator = ASTEvaluator::with_seed(7);
+    for _ in 0..1000 {
+        let value = evaluator.evaluate(&parse("rand()")).unwrap();
+        assert!((0.0..1.0).contains(&value));
+        let value = evaluator.evaluate(&parse("rand(-2, 3)")).unwrap();
+        assert!((-2.0..3.0).contains(&value));
+    }
+
+    let ast = parse("rand(1, 2) + rand()");
+    assert_eq!(
+        ASTEvaluator::with_seed(5).evaluate(&ast),
+        ASTEvaluator::with_seed(5)
+            .evaluate_iterative(&ast)
+            .and_then(|value| value.into_number())
+    );
+}
+
+#[test]
+fn rand_checks_its_arguments() {
+    assert!(matches!(
+        parse("rand(1)").evaluate(),
+        Err(EvalError::ArityMismatch {
+            expected: 2,
+            found: 1,
+            ..
+        })
+    ));
+    assert_eq!(
+        parse("rand(1 < 2, 3)").evaluate(),
+        Err(EvalError::TypeMismatch)
+    );
+    assert_eq!(evaluate("def rand() = 4; rand()"), 4.0);
+}
+
+#[test]
+fn boolean_literals() {
+    assert_eq!(parse("true").evaluate_value(), Ok(Value::Bool(true)));
+    assert_eq!(
+        ASTEvaluator::new().evaluate_iterative(&parse("false")),
+        Ok(Value::Bool(false))
+    );
+    assert_eq!(evaluate("if true then 1 else 2"), 1.0);
+    assert_eq!(
+        parse("if false then 1 else 2").evaluate_iterative(),
+        Ok(2.0)
+    );
+    assert_eq!(parse("true + 1").evaluate(), Err(EvalError::TypeMismatch));
+    assert_eq!(parse("true").evaluate_int(IntOverflow::Checked), Ok(1));
+}
+
+#[test]
+fn quoted_identifiers() {
+    assert_eq!(
+        parse("`total amount` * 2").evaluate_with([("total amount".to_string(), 4.0)].into()),
+        Ok(8.0)
+    );
+    assert_eq!(
+        evaluate("var `a b` = 3; def `f g`(`x y`) = `x y` + 1; `f g`(`a b`)"),
+        4.0
+    );
+}
+
+#[test]
+fn safediv_builtin() {
+    assert_eq!(evaluate("safediv(1, 0, 99)"), 99.0);
+    assert_eq!(evaluate("safediv(6, 2, 99)"), 3.0);
+    assert_eq!(
+        parse("safediv(1, 0, 99) == 99").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+    assert_eq!(
+        parse("safediv(1, 1 - 1, -1)").evaluate_iterative(),
+        Ok(-1.0)
+    );
+    // The fallback is evaluated even when it is not needed.
+    assert_eq!(
+        parse("safediv(6, 2, 1 / 0)").evaluate_iterative(),
+        Err(EvalError::DivisionByZero)
+    );
+    assert!(matches!(
+        parse("safediv(1, 2)").evaluate(),
+        Err(EvalError::ArityMismatch {
+            expected: 3,
+            found: 2,
+            ..
+        })
+    ));
+    assert_eq!(evaluate("def safediv(a, b, c) = c; safediv(6, 2, 5)"), 5.0);
+}
@@ -0,0 +1,82 @@
+use rust::ast::Value;
+use rust::format::{FormatWarning, Radix, ResultFormatter};
+
+#[test]
+fn integers_print_in_the_chosen_radix() {
+    let format = |radix, value| ResultFormatter::with_radix(radix).format(value);
+    assert_eq!(format(Radix::Binary, 5.0), ("0b101".to_string(), None));
+    assert_eq!(format(Radix::Octal, 64.0), ("0o100".to_string(), None));
+    assert_eq!(format(Radix::Decimal, 42.0), ("42".to_string(), None));
+    assert_eq!(
+        format(Radix::Hexadecimal, -255.0),
+        ("-0xFF".to_string(), None)
+    );
+}
+
+#[test]
+fn fractions_fall_back_to_decimal_with_a_warning() {
+    let formatter = ResultFormatter::with_radix(Radix::Hexadecimal);
+    assert_eq!(
+        formatter.format(2.5),
+        (
+            "2.5".to_string(),
+            Some(FormatWarning::NotAnInteger {
+                value: 2.5,
+                radix: Radix::Hexadecimal
+            })
+        )
+    );
+}
+
+#[test]
+fn radix_from_base() {
+    assert_eq!(Radix::from_base(16), Some(Radix::Hexadecimal));
+    assert_eq!(Radix::from_base(2), Some(Radix::Binary));
+    assert_eq!(Radix::from_base(3), None);
+}
+
+#[test]
+fn booleans_print_as_words() {
+    let formatter = ResultFormatter::new();
+    assert_eq!(
+        formatter.format_value(&Value::Bool(true)),
+        ("true".to_string(), None)
+    );
+    assert_eq!(
+        formatter.format_value(&Value::Number(2.0)),
+        ("2".to_string(), None)
+    );
+}
+
+#[test]
+fn round_to_fixes_the_decimal_places() {
+    let mut formatter = ResultFormatter::new();
+    assert_eq!(formatter.format(10.0 / 3.0).0, "3.3333333333333335");
+    formatter.round_to = Some(2);
+    assert_eq!(formatter.format(10.0 / 3.0).0, "3.33");
+    assert_eq!(formatter.format(6.0).0, "6.00");
+    formatter.round_to = Some(0);
+    assert_eq!(formatter.format(-7.6).0, "-8");
+
+    let mut formatter = ResultFormatter::with_radix(Radix::Hexadecimal);
+    formatter.round_to = Some(1);
+    assert_eq!(formatter.format(255.0).0, "0xFF");
+    assert_eq!(formatter.format(0.25).0, "0.2");
+}
+
+#[test]
+fn group_digits_separates_thousands() {
+    let mut formatter = ResultFormatter::new();
+    formatter.group_digits = true;
+    assert_eq!(formatter.format(1000000.5).0, "1,000,000.5");
+    assert_eq!(formatter.format(-1234.0).0, "-1,234");
+    assert_eq!(formatter.format(999.0).0, "999");
+    assert_eq!(formatter.format(0.123456).0, "0.123456");
+    assert_eq!(formatter.format(f64::INFINITY).0, "inf");
+
+    formatter.group_separator = '_';
+    formatter.round_to = Some(2);
+    assert_eq!(formatter.format(123456.789).0, "123_456.79");
+    formatter.radix = Radix::Hexadecimal;
+    assert_eq!(formatter.format(65536.0).0, "0x10000");
+}
@@ -0,0 +1,370 @@
+use rust::ast::lexer::{LexError, Lexer, TextSpan, Token, TokenKind};
+
+fn lex(input: &str) -> Vec<Token> {
+    let (tokens, errors) = Lexer::tokenize(input);
+    assert!(errors.is_empty(), "input: {}", input);
+    tokens
+}
+
+fn token(kind: TokenKind, start: usize, literal: &str) -> Token {
+    Token::new(
+        kind,
+        TextSpan::new(start, start + literal.len(), literal.to_string()),
+    )
+}
+
+#[test]
+fn var_and_def_lex_to_their_own_kinds() {
+    let tokens = lex("var def");
+    assert_eq!(tokens[0], token(TokenKind::Var, 0, "var"));
+    assert_eq!(tokens[1], token(TokenKind::Define, 4, "def"));
+}
+
+#[test]
+fn keyword_prefixes_stay_variables() {
+    let tokens = lex("vars define");
+    assert_eq!(
+        tokens[0],
+        token(TokenKind::Variable("vars".to_string()), 0, "vars")
+    );
+    assert_eq!(
+        tokens[1],
+        token(TokenKind::Variable("define".to_string()), 5, "define")
+    );
+}
+
+#[test]
+fn second_decimal_point_is_a_lex_error() {
+    let (tokens, errors) = Lexer::tokenize("1.2.3");
+    assert_eq!(
+        errors,
+        vec![LexError::MalformedNumber(TextSpan::new(
+            0,
+            5,
+            "1.2.3".to_string()
+        ))]
+    );
+    assert_eq!(tokens[0], token(TokenKind::Bad, 0, "1.2.3"));
+    assert!(rust::try_eval("1.2.3").is_err());
+}
+
+#[test]
+fn block_comments_are_skipped() {
+    assert_eq!(
+        lex("1 /* two\n lines */ + 3"),
+        vec![
+            token(TokenKind::Real(1.0, None), 0, "1"),
+            token(TokenKind::Plus, 19, "+"),
+            token(TokenKind::Real(3.0, None), 21, "3"),
+        ]
+    );
+    assert_eq!(rust::try_eval("2 /* * 100 */ * 3"), Ok(6.0));
+}
+
+#[test]
+fn unterminated_block_comment_is_a_lex_error() {
+    let (_, errors) = Lexer::tokenize("1 + /* open");
+    assert_eq!(
+        errors,
+        vec![LexError::UnterminatedComment(TextSpan::new(
+            4,
+            11,
+            "/* open".to_string()
+        ))]
+    );
+}
+
+#[test]
+fn tokenize_collects_tokens_and_errors() {
+    assert_eq!(
+        lex("1 + 2"),
+        vec![
+            token(TokenKind::Real(1.0, None), 0, "1"),
+            token(TokenKind::Plus, 2, "+"),
+            token(TokenKind::Real(2.0, None), 4, "2"),
+        ]
+    );
+
+    let (_, errors) = Lexer::tokenize("1 $ 2 /* x");
+    assert_eq!(
+        errors,
+        vec![
+            LexError::BadCharacter(TextSpan::new(2, 3, "$".to_string())),
+            LexError::UnterminatedComment(TextSpan::new(6, 10, "/* x".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn case_insensitive_keywords() {
+    let mut lexer = Lexer::new("FOR Print");
+    lexer.case_insensitive_keywords = true;
+    assert_eq!(lexer.next_token(), Some(token(TokenKind::For, 0, "FOR")));
+    lexer.next_token();
+    assert_eq!(
+        lexer.next_token(),
+        Some(token(TokenKind::Print, 4, "Print"))
+    );
+
+    assert_eq!(
+        lex("FOR var"),
+        vec![
+            token(TokenKind::Variable("FOR".to_string()), 0, "FOR"),
+            token(TokenKind::Var, 4, "var"),
+        ]
+    );
+}
+
+#[test]
+fn configurable_decimal_separator() {
+    let mut lexer = Lexer::new("2,5");
+    lexer.decimal_separator = ',';
+    assert_eq!(
+        lexer.next_token(),
+        Some(token(TokenKind::Real(2.5, None), 0, "2,5"))
+    );
+
+    let mut lexer = Lexer::new("2.5");
+    lexer.decimal_separator = ',';
+    assert_eq!(
+        lexer.next_token(),
+        Some(token(TokenKind::Real(2.0, None), 0, "2"))
+    );
+
+    assert_eq!(
+        lex("2.5"),
+        vec![token(TokenKind::Real(2.5, None), 0, "2.5")]
+    );
+}
+
+#[test]
+fn two_character_operators_span_both_characters() {
+    assert_eq!(
+        lex("2 ** 3 // 4"),
+        vec![
+            token(TokenKind::Real(2.0, None), 0, "2"),
+            token(TokenKind::Pow, 2, "**"),
+            token(TokenKind::Real(3.0, None), 5, "3"),
+            token(TokenKind::IntegerDivide, 7, "//"),
+            token(TokenKind::Real(4.0, None), 10, "4"),
+        ]
+    );
+}
+
+#[test]
+fn token_histogram_counts_kinds() {
+    let histogram = Lexer::token_histogram("1 + 2 + 3");
+    assert_eq!(histogram["Real"], 3);
+    assert_eq!(histogram["Plus"], 2);
+    assert_eq!(histogram.len(), 2);
+}
+
+#[test]
+fn preserved_comments_are_emitted_as_tokens() {
+    let mut lexer = Lexer::new("1 /* one */ + 2");
+    lexer.preserve_comments = true;
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    assert_eq!(tokens[2], token(TokenKind::Comment, 2, "/* one */"));
+
+    assert!(!lex("1 /* one */ + 2").contains(&tokens[2]));
+}
+
+#[test]
+fn identifiers_longer_than_the_limit_are_errors() {
+    let long = "a".repeat(300);
+    let (tokens, errors) = Lexer::tokenize(&format!("{} + 1", long));
+    assert_eq!(
+        errors,
+        vec![LexError::IdentifierTooLong(TextSpan::new(
+            0,
+            256,
+            "a".repeat(256)
+        ))]
+    );
+    assert_eq!(tokens[0], token(TokenKind::Bad, 0, &"a".repeat(256)));
+    assert_eq!(tokens[1], token(TokenKind::Plus, 301, "+"));
+
+    let (tokens, _) = Lexer::tokenize(&"b".repeat(1_000_000));
+    assert_eq!(tokens[0], token(TokenKind::Bad, 0, &"b".repeat(256)));
+
+    let mut lexer = Lexer::new("abcd abc");
+    lexer.max_identifier_len = 3;
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    assert_eq!(lexer.errors().len(), 1);
+    assert_eq!(tokens[0], token(TokenKind::Bad, 0, "abc"));
+    assert_eq!(
+        tokens[2],
+        token(TokenKind::Variable("abc".to_string()), 5, "abc")
+    );
+}
+
+#[test]
+fn peek_token_does_not_advance() {
+    let mut lexer = Lexer::new("12 + /* c */ x");
+    let first = lexer.peek_token().cloned();
+    assert_eq!(first, Some(token(TokenKind::Real(12.0, None), 0, "12")));
+    assert_eq!(lexer.peek_token().cloned(), first);
+
+    loop {
+        let peeked = lexer.peek_token().cloned();
+        let next = lexer.next_token();
+        assert_eq!(peeked, next);
+        if next.is_none() {
+            break;
+        }
+    }
+    assert!(lexer.peek_token().is_none());
+}
+
+#[test]
+fn dangling_decimal_point_is_a_lex_error() {
+    let (tokens, errors) = Lexer::tokenize("1. 5");
+    assert_eq!(
+        errors,
+        vec![LexError::DanglingDecimalPoint(TextSpan::new(
+            0,
+            2,
+            "1.".to_string()
+        ))]
+    );
+    assert_eq!(
+        tokens,
+        vec![
+            token(TokenKind::Bad, 0, "1."),
+            token(TokenKind::Real(5.0, None), 3, "5"),
+        ]
+    );
+
+    assert_eq!(
+        lex("1 000"),
+        vec![
+            token(TokenKind::Real(1.0, None), 0, "1"),
+            token(TokenKind::Real(0.0, None), 2, "000"),
+        ]
+    );
+}
+
+#[test]
+fn number_tokens_keep_their_source_text() {
+    assert_eq!(
+        lex("5 + 12"),
+        vec![
+            token(TokenKind::Real(5.0, None), 0, "5"),
+            token(TokenKind::Plus, 2, "+"),
+            token(TokenKind::Real(12.0, None), 4, "12"),
+        ]
+    );
+
+    let mut lexer = Lexer::new("3,50");
+    lexer.decimal_separator = ',';
+    assert_eq!(
+        lexer.next_token(),
+        Some(token(TokenKind::Real(3.5, None), 0, "3,50"))
+    );
+}
+
+#[test]
+fn tokenize_leaves_out_eof() {
+    assert!(lex("").is_empty());
+
+    let mut lexer = Lexer::new("1 + 2");
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    let eof = tokens.pop().unwrap();
+    assert_eq!(
+        eof,
+        Token::new(TokenKind::Eof, TextSpan::new(5, 5, "\0".to_string()))
+    );
+    assert!(!lex("1 + 2").contains(&eof));
+}
+
+#[test]
+fn line_column_handles_every_line_ending() {
+    let input = "1 +\r\n2 *\r\n\t3";
+    let mut lexer = Lexer::new(input);
+    assert_eq!(lexer.line_column(0), (1, 1));
+    assert_eq!(lexer.line_column(2), (1, 3));
+    assert_eq!(lexer.line_column(5), (2, 1));
+    assert_eq!(lexer.line_column(input.find('3').unwrap()), (3, 5));
+    lexer.tab_width = 8;
+    assert_eq!(lexer.line_column(input.find('3').unwrap()), (3, 9));
+
+    assert_eq!(Lexer::new("1 +\n2 *\n\t3").line_column(9), (3, 5));
+    assert_eq!(Lexer::new("a\rb").line_column(2), (2, 1));
+    assert_eq!(lex(input).len(), 5);
+}
+
+#[test]
+fn doc_comments_are_trimmed() {
+    assert_eq!(
+        lex("## hi  \n1"),
+        vec![
+            token(TokenKind::DocComment("hi".to_string()), 0, "## hi  "),
+            token(TokenKind::Real(1.0, None), 8, "1"),
+        ]
+    );
+}
+
+#[test]
+fn backticks_quote_identifiers() {
+    assert_eq!(
+        lex("`total amount` + 1")[0],
+        token(
+            TokenKind::Variable("total amount".to_string()),
+            0,
+            "`total amount`"
+        )
+    );
+    assert_eq!(
+        lex("`if`"),
+        vec![token(TokenKind::Variable("if".to_string()), 0, "`if`")]
+    );
+
+    let (tokens, errors) = Lexer::tokenize("1 + `total");
+    assert_eq!(
+        errors,
+        vec![LexError::UnterminatedIdentifier(TextSpan::new(
+            4,
+            10,
+            "`total".to_string()
+        ))]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "unterminated quoted identifier starting at 4"
+    );
+    assert_eq!(tokens[2], token(TokenKind::Bad, 4, "`total"));
+}
+
+#[test]
+fn two_character_operators_take_precedence() {
+    for (two, one, two_kind, one_kind) in [
+        ("**", "*", TokenKind::Pow, TokenKind::Asterisk),
+        ("==", "=", TokenKind::EqualEqual, TokenKind::Assing),
+        ("<=", "<", TokenKind::LessEqual, TokenKind::Less),
+        (">=", ">", TokenKind::GreaterEqual, TokenKind::Greater),
+        ("!=", "!", TokenKind::BangEqual, TokenKind::Bang),
+        ("//", "/", TokenKind::IntegerDivide, TokenKind::Slash),
+    ] {
+        assert_eq!(
+            lex(&format!("{}{}", two, one)),
+            vec![token(two_kind, 0, two), token(one_kind.clone(), 2, one)]
+        );
+        assert_eq!(
+            lex(&format!("{} {}", one, one)),
+            vec![token(one_kind.clone(), 0, one), token(one_kind, 2, one)]
+        );
+    }
+    assert_eq!(
+        lex("/* c */ 1"),
+        vec![token(TokenKind::Real(1.0, None), 8, "1")]
+    );
+}
@@ -0,0 +1,24 @@
+use std::process::Command;
+
+// Builds the library the way a `no_std` user would, with default features
+// off, in a target directory of its own so it does not contend for the lock
+// held by the running `cargo test`.
+#[test]
+fn library_builds_without_std() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args(["build", "--lib", "--no-default-features"])
+        .current_dir(manifest_dir)
+        .env(
+            "CARGO_TARGET_DIR",
+            format!("{}/target/no_std", manifest_dir),
+        )
+        .output()
+        .expect("failed to run cargo");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,408 @@
+use rust::ast::lexer::{Lexer, TextSpan, Token, TokenKind};
+use rust::ast::parser::{ParseError, Parser};
+use rust::ast::{Ast, Value};
+
+// Every token the lexer produces, whitespace and comments included.
+fn lex_with_trivia(input: &str) -> Vec<Token> {
+    let mut lexer = Lexer::new(input);
+    lexer.preserve_comments = true;
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn parse_errors(input: &str) -> Vec<ParseError> {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    while parser.next_statement().is_some() {}
+    parser.errors().to_vec()
+}
+
+fn parse_with_equality(input: &str) -> Ast {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    parser.equals_is_equality = true;
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert!(parser.errors().is_empty(), "input: {}", input);
+    ast
+}
+
+fn parse_with_trivia(input: &str) -> Ast {
+    let mut parser = Parser::with_trivia(lex_with_trivia(input));
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert!(parser.errors().is_empty(), "input: {}", input);
+    ast
+}
+
+#[test]
+fn assignment_with_trivia() {
+    assert_eq!(parse_with_trivia("x = 1; x").evaluate(), Ok(1.0));
+    assert_eq!(
+        parse_with_trivia("x /* one */ = 1; x + 1").evaluate(),
+        Ok(2.0)
+    );
+}
+
+#[test]
+fn remaining_tokens_after_a_statement() {
+    let (tokens, _) = Lexer::tokenize("1 + 2 ; 3");
+    let mut parser = Parser::new(tokens);
+    parser.next_statement().unwrap();
+    assert_eq!(
+        parser.remaining_tokens(),
+        [
+            Token::new(TokenKind::Term, TextSpan::new(6, 7, ";".to_string())),
+            Token::new(
+                TokenKind::Real(3.0, None),
+                TextSpan::new(8, 9, "3".to_string())
+            ),
+        ]
+    );
+    assert!(!parser.is_at_end());
+
+    parser.next_statement().unwrap();
+    assert!(parser.remaining_tokens().is_empty());
+    assert!(parser.is_at_end());
+}
+
+#[test]
+fn malformed_input_reports_errors_without_panicking() {
+    for input in ["+", ")", "(", "1 +", "var", "def f(", "|"] {
+        assert!(!parse_errors(input).is_empty(), "input: {}", input);
+    }
+}
+
+#[test]
+fn trailing_operator_is_reported() {
+    assert_eq!(
+        parse_errors("2 +"),
+        vec![ParseError::MissingRightOperand(TextSpan::new(
+            2,
+            3,
+            "+".to_string()
+        ))]
+    );
+    assert!(matches!(
+        parse_errors("1 + + 2")[0],
+        ParseError::UnexpectedToken(_)
+    ));
+    assert!(parse_errors("var a = 1; a").is_empty());
+}
+
+#[test]
+fn operator_before_a_top_level_comma_is_missing_its_operand() {
+    assert!(matches!(
+        parse_errors("1 +, 2")[0],
+        ParseError::MissingRightOperand(_)
+    ));
+}
+
+#[test]
+fn with_trivia_reports_spans_in_the_raw_input() {
+    let mut parser = Parser::with_trivia(lex_with_trivia("  \n 1 + )"));
+    assert!(parser.next_statement().is_none());
+    assert_eq!(
+        parser.errors(),
+        [ParseError::MissingRightOperand(TextSpan::new(
+            6,
+            7,
+            "+".to_string()
+        ))]
+    );
+
+    assert_eq!(parse_with_trivia(" 1 + 2 ").evaluate(), Ok(3.0));
+}
+
+#[test]
+fn with_trivia_skips_preserved_comments() {
+    assert_eq!(parse_with_trivia("1 /* one */ + 2").evaluate(), Ok(3.0));
+}
+
+#[test]
+fn is_incomplete() {
+    assert!(Parser::is_incomplete("(1 + 2"));
+    assert!(Parser::is_incomplete("{1 + |2"));
+    assert!(Parser::is_incomplete("if 1 then 2"));
+    assert!(Parser::is_incomplete("1 /* open"));
+    assert!(Parser::is_incomplete("1 +"));
+    assert!(Parser::is_incomplete("var x = 2 *"));
+
+    assert!(!Parser::is_incomplete("(1 + 2)"));
+    assert!(!Parser::is_incomplete("(1 + 2))"));
+    assert!(!Parser::is_incomplete("(1 + 2 3"));
+    assert!(!Parser::is_incomplete("1 + + 2"));
+    assert!(!Parser::is_incomplete("1 + )"));
+    assert!(!Parser::is_incomplete("1 +; 2"));
+}
+
+#[test]
+fn equals_can_mean_equality() {
+    assert_eq!(
+        parse_with_equality("2 + 2 = 4").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+    assert_eq!(
+        parse_with_equality("2 + 2 = 5").evaluate_value(),
+        Ok(Value::Bool(false))
+    );
+    // A statement-level `=` still assigns.
+    assert_eq!(
+        parse_with_equality("x = 3; var y = x = 3; y").evaluate_value(),
+        Ok(Value::Bool(true))
+    );
+}
+
+#[test]
+fn from_tokens_rejects_bad_tokens() {
+    let (tokens, _) = Lexer::tokenize("1 + 2");
+    assert!(Parser::from_tokens(tokens).is_ok());
+
+    let (tokens, _) = Lexer::tokenize("1 + $ 2 # 3");
+    assert_eq!(
+        Parser::from_tokens(tokens).err(),
+        Some(ParseError::BadToken(TextSpan::new(4, 5, "$".to_string())))
+    );
+}
+
+#[test]
+fn reparse_from_keeps_statements_before_the_edit() {
+    let (tokens, _) = Lexer::tokenize("1 + 2; 3 * 4");
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+
+    let (tokens, _) = Lexer::tokenize("1 + 2; 3 * 5");
+    assert_eq!(parser.reparse_from(11, tokens), 1);
+    ast.statements.truncate(1);
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert_eq!(ast.evaluate_all(), Ok(vec![3.0, 15.0]));
+
+    // Replacing the `;` joins the statements, so nothing is kept.
+    let (tokens, _) = Lexer::tokenize("1 + 2+ 3 * 5");
+    assert_eq!(parser.reparse_from(5, tokens), 0);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert_eq!(ast.evaluate_all(), Ok(vec![18.0]));
+}
+
+#[test]
+fn flat_precedence_evaluates_left_to_right() {
+    let parse_flat = |input: &str| {
+        let (tokens, _) = Lexer::tokenize(input);
+        let mut parser = Parser::new(tokens);
+        parser.flat_precedence = true;
+        let mut ast = Ast::new();
+        while let Some(statement) = parser.next_statement() {
+            ast.add_statement(statement);
+        }
+        ast
+    };
+    assert_eq!(parse_flat("2 + 3 * 4").evaluate(), Ok(20.0));
+    assert_eq!(parse_flat("2 ^ 3 ^ 2").evaluate(), Ok(64.0));
+    assert_eq!(parse_flat("10 - 4 - 3").evaluate(), Ok(3.0));
+    assert_eq!(parse_flat("-2 * 3 + 1").evaluate(), Ok(-5.0));
+    assert_eq!(parse_flat("2 * (3 + 4)").evaluate(), Ok(14.0));
+}
+
+#[test]
+fn expect_reports_the_missing_token() {
+    assert!(parse_errors("(1 + 2)").is_empty());
+    assert!(parse_errors("def f(a, b) = a + b").is_empty());
+
+    assert_eq!(
+        parse_errors("(1 + 2 3"),
+        vec![ParseError::Expected {
+            expected: TokenKind::RightParen,
+            found: Token::new(
+                TokenKind::Real(3.0, None),
+                TextSpan::new(7, 8, "3".to_string())
+            ),
+        }]
+    );
+    assert!(matches!(
+        &parse_errors("if 1 2 else 3")[0],
+        ParseError::Expected {
+            expected: TokenKind::Then,
+            ..
+        }
+    ));
+    assert!(matches!(
+        &parse_errors("def f(a b) = a")[0],
+        ParseError::Expected {
+            expected: TokenKind::RightParen,
+            ..
+        }
+    ));
+    assert_eq!(parse_errors("(1 + 2"), vec![ParseError::UnexpectedEof]);
+}
+
+#[test]
+fn print_needs_an_argument() {
+    assert!(parse_errors("print 1, x").is_empty());
+    assert!(!parse_errors("print").is_empty());
+}
+
+#[test]
+fn blocks_hold_statements() {
+    assert!(parse_errors("{ a = 1; a + 2; }").is_empty());
+    assert!(!parse_errors("{ 1 2 }").is_empty());
+    assert_eq!(parse_errors("{ 1; 2"), vec![ParseError::UnexpectedEof]);
+}
+
+#[test]
+fn percent_follows_a_number() {
+    assert!(parse_errors("5%").is_empty());
+    assert!(!parse_errors("x%").is_empty());
+}
+
+#[test]
+fn parse_single_expression() {
+    let parse_one = |input: &str| {
+        let (tokens, _) = Lexer::tokenize(input);
+        Parser::new(tokens).parse_single_expression()
+    };
+    assert!(parse_one("1 + 2").is_ok());
+    assert_eq!(
+        parse_one("1 + 2 3").err(),
+        Some(ParseError::TrailingTokens(TextSpan::new(
+            6,
+            7,
+            "3".to_string()
+        )))
+    );
+    assert!(matches!(
+        parse_one("1;"),
+        Err(ParseError::TrailingTokens(_))
+    ));
+    assert_eq!(parse_one("").err(), Some(ParseError::UnexpectedEof));
+
+    assert!(Parser::with_trivia(lex_with_trivia("1 + 2 "))
+        .parse_single_expression()
+        .is_ok());
+}
+
+#[test]
+fn doc_comments_attach_to_the_next_statement() {
+    let (tokens, _) = Lexer::tokenize(
+        "## title: area\nvar area = 3 * 4;\n## old\n## units: m2\narea * 2; area\n## dangling",
+    );
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    assert!(parser.errors().is_empty());
+    assert_eq!(ast.len(), 3);
+    assert_eq!(ast.statements[0].doc.as_deref(), Some("title: area"));
+    assert_eq!(ast.statements[1].doc.as_deref(), Some("units: m2"));
+    assert_eq!(ast.statements[2].doc, None);
+
+    assert!(!parse_errors("1 + ## x\n 2").is_empty());
+}
+
+#[test]
+fn boolean_literals_cannot_be_bound() {
+    assert!(!parse_errors("var true = 1").is_empty());
+}
+
+#[test]
+fn unterminated_quoted_identifier_is_incomplete() {
+    assert!(Parser::is_incomplete("`total"));
+}
+
+#[test]
+fn statement_limit() {
+    let count_statements = |input: &str, limit: usize| {
+        let (tokens, _) = Lexer::tokenize(input);
+        let mut parser = Parser::new(tokens);
+        parser.max_statements = limit;
+        let mut count = 0;
+        while parser.next_statement().is_some() {
+            count += 1;
+        }
+        (count, parser.errors().to_vec())
+    };
+    assert_eq!(count_statements("1; 2; 3", 3), (3, vec![]));
+    assert_eq!(count_statements("1; 2; 3;;", 3), (3, vec![]));
+    assert_eq!(
+        count_statements("1; 2; 3; 4; 5", 3),
+        (3, vec![ParseError::TooManyStatements])
+    );
+    assert_eq!(
+        count_statements("1", 0),
+        (0, vec![ParseError::TooManyStatements])
+    );
+    assert_eq!(count_statements("", 0), (0, vec![]));
+}
+
+#[test]
+fn statements_span_their_source() {
+    let statement_spans = |input: &str| {
+        let (tokens, _) = Lexer::tokenize(input);
+        let mut parser = Parser::new(tokens);
+        let mut spans = Vec::new();
+        while let Some(statement) = parser.next_statement() {
+            spans.push(statement.span());
+        }
+        spans
+    };
+    assert_eq!(
+        statement_spans("var a = 1;\nb + 2;"),
+        vec![
+            TextSpan::new(0, 9, "var a = 1".to_string()),
+            TextSpan::new(11, 16, "b + 2".to_string()),
+        ]
+    );
+    // Doc comments and trailing comments are left out.
+    assert_eq!(
+        statement_spans("## doc\n  f(1,\n2) /* c */ ; x"),
+        vec![
+            TextSpan::new(9, 16, "f(1, 2)".to_string()),
+            TextSpan::new(27, 28, "x".to_string()),
+        ]
+    );
+
+    let mut parser = Parser::with_trivia(lex_with_trivia("1 /* c */ + 2 ; 3"));
+    assert_eq!(
+        parser.next_statement().unwrap().span(),
+        TextSpan::new(0, 13, "1 /* c */ + 2".to_string())
+    );
+}
+
+#[test]
+fn hand_built_spans_do_not_panic() {
+    let at = |kind: TokenKind, start: usize, literal: &str| {
+        Token::new(kind, TextSpan::new(start, start, literal.to_string()))
+    };
+    let tokens = vec![
+        at(TokenKind::Real(1.0, None), 0, "1"),
+        at(TokenKind::Plus, 0, "+"),
+        at(TokenKind::Real(2.0, None), 0, "2"),
+    ];
+    let mut parser = Parser::new(tokens);
+    let statement = parser.next_statement().unwrap();
+    let mut ast = Ast::new();
+    ast.add_statement(statement);
+    assert_eq!(ast.evaluate(), Ok(3.0));
+
+    let tokens = vec![
+        at(TokenKind::Real(1.0, None), 9, "1"),
+        at(TokenKind::Plus, 5, "+"),
+        at(TokenKind::Real(2.0, None), 1, "2"),
+    ];
+    assert!(Parser::new(tokens).next_statement().is_some());
+}
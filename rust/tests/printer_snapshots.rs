@@ -0,0 +1,221 @@
+use rust::ast::lexer::Lexer;
+use rust::ast::parser::Parser;
+use rust::ast::{ASTPrinter, ASTXMLPrinter, Ast};
+
+fn parse(input: &str) -> Ast {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    ast
+}
+
+fn assert_text(input: &str, expected: &str) {
+    assert_eq!(
+        parse(input).visualize_string(),
+        expected,
+        "input: {}",
+        input
+    );
+}
+
+fn assert_xml(input: &str, expected_body: &str) {
+    let expected = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<program>\n{}</program>",
+        expected_body
+    );
+    assert_eq!(
+        parse(input).visualize_xml_string(),
+        expected,
+        "input: {}",
+        input
+    );
+}
+
+#[test]
+fn text_number() {
+    assert_text("42", "Real(42.0)");
+    assert_text("1.5", "Real(1.5)");
+}
+
+#[test]
+fn text_boolean() {
+    assert_text("true", "Bool(true)");
+    assert_text("false", "Bool(false)");
+}
+
+#[test]
+fn text_binary_and_parens() {
+    assert_text(
+        "2 + 3 * (2 + 4)",
+        "Plus(Real(2.0),Multiply(Real(3.0),(Plus(Real(2.0),Real(4.0)))))",
+    );
+}
+
+#[test]
+fn text_absolute_and_calls() {
+    assert_text("|x - 5|", "|Minus(Variable(x),Real(5.0))|");
+    assert_text("f(g(1), 2)", "f(g(Real(1.0)),Real(2.0))");
+}
+
+#[test]
+fn text_negation() {
+    assert_text("-5", "Neg(Real(5.0))");
+    assert_text("-2 * 3", "Multiply(Neg(Real(2.0)),Real(3.0))");
+}
+
+#[test]
+fn text_function_declaration() {
+    assert_text(
+        "def f(x, y) = x * y",
+        "Def f(x,y)=Multiply(Variable(x),Variable(y))",
+    );
+}
+
+#[test]
+fn xml_binary() {
+    assert_xml(
+        "1 + 2 * 3",
+        "<add>\n<real>1.0</real>\n\n<mul>\n<real>2.0</real>\n\n<real>3.0</real>\n</mul>\n</add>\n",
+    );
+}
+
+#[test]
+fn xml_parens_and_abs() {
+    assert_xml(
+        "(|a|)",
+        "<paren><abs>\n<variable>a</variable>\n</abs>\n</paren>\n",
+    );
+}
+
+#[test]
+fn xml_call() {
+    assert_xml(
+        "max(1, b)",
+        "<call name=\"max\">\n<real>1.0</real>\n<variable>b</variable>\n</call>\n",
+    );
+}
+
+#[test]
+fn xml_negation() {
+    assert_xml("-5", "<neg>\n<real>5.0</real>\n</neg>\n");
+}
+
+#[test]
+fn xml_boolean() {
+    assert_xml("true", "<bool>true</bool>\n");
+}
+
+#[test]
+fn xml_escapes_quoted_names() {
+    assert_xml(
+        "var `a<b` = `x & \"y\"`",
+        "<var name=\"a&lt;b\">\n<variable>x &amp; &quot;y&quot;</variable>\n</var>\n",
+    );
+    assert_xml(
+        "`f<>`(1)",
+        "<call name=\"f&lt;&gt;\">\n<real>1.0</real>\n</call>\n",
+    );
+    assert_xml(
+        "def `f'`(`a&`) = 1",
+        "<def name=\"f&apos;\">\n<param>a&amp;</param>\n<real>1.0</real>\n</def>\n",
+    );
+}
+
+#[test]
+fn print_to_string_buffer() {
+    let ast = parse("1 + x");
+    let mut buffer = String::from("> ");
+    ASTPrinter::new().print_to(&ast, &mut buffer).unwrap();
+    assert_eq!(buffer, "> Plus(Real(1.0),Variable(x))");
+
+    let mut xml = String::new();
+    ASTXMLPrinter::new().print_to(&ast, &mut xml).unwrap();
+    assert_eq!(xml, ast.visualize_xml_string());
+}
+
+#[test]
+fn visualization_width() {
+    // "Plus(Real(1.0),Variable(x))"
+    assert_eq!(parse("1 + x").visualization_width(), 27);
+    assert_eq!(parse("").visualization_width(), 0);
+}
+
+#[test]
+fn visualization_width_counts_chars() {
+    // "Real(1.0)Plus(Real(1.0),Variable(x))Var é=Real(2.0)"
+    let ast = parse("1; 1 + x; var é = 2");
+    assert_eq!(ast.visualization_width(), 51);
+    assert_eq!(ast.visualize_string().len(), 52);
+}
+
+#[test]
+fn source_round_trip() {
+    for input in [
+        "1 + 2 * 3",
+        "(1 + 2) * 3",
+        "2 ^ 3 ^ 2",
+        "(2 ^ 3) ^ 2",
+        "10 - (4 - 3)",
+        "-x ^ 2",
+        "(-2) ^ 2",
+        "|x - 5|! + {y}",
+        "var a = f(1, g(2)); def f(x, y) = if x < y then x else y",
+        "if true then 1 else false",
+        "var `total amount` = 3; `total amount` * `if`",
+        "var x = 1; {x = 2}; x",
+        "a = 2; print a * 2, 1 < 2; print a",
+        "200 + 5%",
+    ] {
+        let source = parse(input).to_source();
+        assert_eq!(source, input);
+        assert_eq!(
+            parse(&source).visualize_string(),
+            parse(input).visualize_string()
+        );
+    }
+}
+
+#[test]
+fn source_adds_needed_parentheses() {
+    use rust::ast::ASTExpression;
+    let sum = ASTExpression::add(ASTExpression::number(1.0), ASTExpression::number(2.0));
+    let product = ASTExpression::mul(sum, ASTExpression::number(3.0));
+    let mut ast = Ast::new();
+    ast.add_statement(rust::ast::ASTStatement::expression(product));
+    assert_eq!(ast.to_source(), "(1 + 2) * 3");
+}
+
+#[test]
+fn source_drops_redundant_parentheses() {
+    assert_eq!(parse("2 - (3 - 4)").to_source(), "2 - (3 - 4)");
+    assert_eq!(parse("(2 - 3) - 4").to_source(), "2 - 3 - 4");
+    assert_eq!(parse("((1)) + (2 * 3)").to_source(), "1 + 2 * 3");
+    assert_eq!(parse("-(2) ^ (2)").to_source(), "-2 ^ 2");
+    assert_eq!(parse("(x)!").to_source(), "x!");
+}
+
+#[test]
+fn operator_kinds_display_as_symbols() {
+    use rust::ast::ASTBinaryOperatorKind;
+    for (kind, symbol) in [
+        (ASTBinaryOperatorKind::Plus, "+"),
+        (ASTBinaryOperatorKind::Minus, "-"),
+        (ASTBinaryOperatorKind::Multiply, "*"),
+        (ASTBinaryOperatorKind::Divide, "/"),
+        (ASTBinaryOperatorKind::Pow, "^"),
+        (ASTBinaryOperatorKind::IntegerDivide, "//"),
+    ] {
+        assert_eq!(kind.to_string(), symbol);
+    }
+}
+
+#[test]
+fn xml_uses_tag_names_for_operators() {
+    assert_xml(
+        "1 - 2 / 3",
+        "<sub>\n<real>1.0</real>\n\n<div>\n<real>2.0</real>\n\n<real>3.0</real>\n</div>\n</sub>\n",
+    );
+}
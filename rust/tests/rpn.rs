@@ -0,0 +1,107 @@
+use rust::ast::lexer::Lexer;
+use rust::ast::parser::Parser;
+use rust::ast::rpn::eval_rpn;
+use rust::ast::rpn::RpnToken::{Absolute, Factorial, Negate, Number, Operator, Variable};
+use rust::ast::{ASTBinaryOperatorKind, Ast, EvalError};
+use std::collections::HashMap;
+
+fn parse(input: &str) -> Ast {
+    let (tokens, _) = Lexer::tokenize(input);
+    let mut parser = Parser::new(tokens);
+    let mut ast = Ast::new();
+    while let Some(statement) = parser.next_statement() {
+        ast.add_statement(statement);
+    }
+    ast
+}
+
+#[test]
+fn operators_follow_their_operands() {
+    assert_eq!(
+        parse("2 + 3 * 4").to_rpn(),
+        Some(vec![
+            Number(2.0),
+            Number(3.0),
+            Number(4.0),
+            Operator(ASTBinaryOperatorKind::Multiply),
+            Operator(ASTBinaryOperatorKind::Plus),
+        ])
+    );
+    assert_eq!(
+        parse("(2 + x) * -|y|!").to_rpn(),
+        Some(vec![
+            Number(2.0),
+            Variable("x".to_string()),
+            Operator(ASTBinaryOperatorKind::Plus),
+            Variable("y".to_string()),
+            Absolute,
+            Factorial,
+            Negate,
+            Operator(ASTBinaryOperatorKind::Multiply),
+        ])
+    );
+}
+
+#[test]
+fn only_single_expressions_convert() {
+    assert_eq!(parse("if 1 then 2 else 3").to_rpn(), None);
+    assert_eq!(parse("def f(a) = a; f(2)").to_rpn(), None);
+    assert_eq!(parse("1; 2").to_rpn(), None);
+}
+
+#[test]
+fn stack_machine_matches_the_tree_evaluator() {
+    let variables: HashMap<String, f64> = [("x".to_string(), 3.0)].into();
+    for input in [
+        "2 + 3 * 4",
+        "(x - 5) * -|x - 10|",
+        "2 ^ x // 3",
+        "x! / 4",
+        "pi * x",
+    ] {
+        let ast = parse(input);
+        assert_eq!(
+            eval_rpn(&ast.to_rpn().unwrap(), &variables),
+            ast.evaluate_with(variables.clone()),
+            "input: {}",
+            input
+        );
+    }
+}
+
+#[test]
+fn stack_machine_rejects_malformed_input() {
+    let variables = HashMap::new();
+    assert_eq!(eval_rpn(&[], &variables), Err(EvalError::MalformedRpn));
+    assert_eq!(
+        eval_rpn(&[Negate], &variables),
+        Err(EvalError::MalformedRpn)
+    );
+    assert_eq!(
+        eval_rpn(
+            &[Number(1.0), Operator(ASTBinaryOperatorKind::Plus)],
+            &variables
+        ),
+        Err(EvalError::MalformedRpn)
+    );
+    assert_eq!(
+        eval_rpn(&[Number(1.0), Number(2.0)], &variables),
+        Err(EvalError::MalformedRpn)
+    );
+
+    assert_eq!(
+        eval_rpn(
+            &[
+                Number(1.0),
+                Number(0.0),
+                Operator(ASTBinaryOperatorKind::Divide),
+            ],
+            &variables
+        ),
+        Err(EvalError::DivisionByZero)
+    );
+    assert!(matches!(
+        eval_rpn(&[Variable("q".to_string())], &variables),
+        Err(EvalError::UndefinedVariable { .. })
+    ));
+}